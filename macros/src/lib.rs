@@ -0,0 +1,385 @@
+//! Attribute and path macros for `xtest-data`.
+//!
+//! Do not depend on this crate directly. Enable the `macros` feature of `xtest-data` instead and
+//! use the re-exports from there, as the expansion refers to items of `xtest_data`.
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use std::path::{Path, PathBuf};
+
+/// Declare a test function that receives registered test data as arguments.
+///
+/// See `xtest_data::test` for the documentation.
+#[proc_macro_attribute]
+pub fn test(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let data = match parse_data(attr) {
+        Ok(data) => data,
+        Err(err) => return err.into_compile_error(),
+    };
+
+    let function = match TestFn::parse(item) {
+        Ok(function) => function,
+        Err(err) => return err.into_compile_error(),
+    };
+
+    function.expand(&data)
+}
+
+/// A path of test data, checked when compiling.
+///
+/// See `xtest_data::path` for the documentation.
+#[proc_macro]
+pub fn path(input: TokenStream) -> TokenStream {
+    // The error is a statement, and this is an expression.
+    let in_block = |err: Error| {
+        let block = Group::new(Delimiter::Brace, err.into_compile_error());
+        TokenStream::from(TokenTree::Group(block))
+    };
+
+    let literal = match parse_path(input) {
+        Ok(literal) => literal,
+        Err(err) => return in_block(err),
+    };
+
+    let value = match check_path(&literal) {
+        Ok(value) => value,
+        Err(err) => return in_block(err),
+    };
+
+    // The path is taken out of a marker, which stays in the compiled test for `pack --no-run`.
+    let mut marker = Literal::string(&format!("{}{}\0", PATH_MARKER, value));
+    marker.set_span(literal.span());
+
+    let mut body: TokenStream = "let marker: &'static str = ::core::hint::black_box"
+        .parse()
+        .unwrap();
+    body.extend([
+        TokenTree::Group(Group::new(
+            Delimiter::Parenthesis,
+            TokenTree::Literal(marker).into(),
+        )),
+        TokenTree::Punct(Punct::new(';', Spacing::Alone)),
+    ]);
+    let path: TokenStream = format!("&marker[{}..marker.len() - 1]", PATH_MARKER.len())
+        .parse()
+        .unwrap();
+    body.extend(path);
+
+    let mut out: TokenStream = "::std::path::PathBuf::from".parse().unwrap();
+    out.extend([TokenTree::Group(Group::new(
+        Delimiter::Parenthesis,
+        TokenTree::Group(Group::new(Delimiter::Brace, body)).into(),
+    ))]);
+    out
+}
+
+/// Precedes each path of `path!` in the compiled code, which ends with a NUL byte.
+///
+/// `cargo xtest-data pack --no-run` searches the compiled tests for it.
+const PATH_MARKER: &str = "\0xtest-data-path:";
+
+struct Error {
+    message: String,
+    span: Span,
+}
+
+/// The function item that we wrap, split into the parts relevant to us.
+struct TestFn {
+    /// Outer attributes, including doc comments. These are kept on the generated test.
+    attrs: Vec<TokenTree>,
+    /// Whether the attributes already include a `#[test]`, see `is_test_attr`.
+    has_test_attr: bool,
+    /// The name of the function.
+    name: Ident,
+    /// Everything between the parameters and the body, i.e. the return type.
+    output: Vec<TokenTree>,
+    /// The complete function item, without its attributes.
+    item: Vec<TokenTree>,
+}
+
+/// Whether an attribute is a test attribute, by the last segment of its path.
+///
+/// This includes `#[test]`, `#[::core::prelude::v1::test]` and those of other crates with
+/// arguments, such as `#[tokio::test(flavor = "multi_thread")]`.
+fn is_test_attr(attr: TokenStream) -> bool {
+    let mut last = None;
+    for tt in attr {
+        match tt {
+            TokenTree::Ident(ident) => last = Some(ident.to_string()),
+            TokenTree::Punct(colon) if colon.as_char() == ':' => {}
+            // The arguments follow the path.
+            TokenTree::Group(_) => break,
+            _ => return false,
+        }
+    }
+
+    last.as_deref() == Some("test")
+}
+
+/// Parse a comma separated list of `data = "path"` pairs.
+fn parse_data(attr: TokenStream) -> Result<Vec<Literal>, Error> {
+    let mut data = vec![];
+    let mut tokens = attr.into_iter();
+
+    loop {
+        let key = match tokens.next() {
+            None => return Ok(data),
+            Some(TokenTree::Ident(key)) if key.to_string() == "data" => key,
+            Some(other) => return Err(Error::new("expected `data = \"path\"`", other.span())),
+        };
+
+        match tokens.next() {
+            Some(TokenTree::Punct(eq)) if eq.as_char() == '=' => {}
+            _ => return Err(Error::new("expected `=` after `data`", key.span())),
+        }
+
+        match tokens.next() {
+            Some(TokenTree::Literal(path)) if is_str_literal(&path) => data.push(path),
+            Some(other) => return Err(Error::new("expected a string literal", other.span())),
+            None => return Err(Error::new("expected a string literal", key.span())),
+        }
+
+        match tokens.next() {
+            None => return Ok(data),
+            Some(TokenTree::Punct(comma)) if comma.as_char() == ',' => {}
+            Some(other) => return Err(Error::new("expected `,`", other.span())),
+        }
+    }
+}
+
+/// Parse a single string literal, with an optional trailing comma.
+fn parse_path(input: TokenStream) -> Result<Literal, Error> {
+    let mut tokens = input.into_iter();
+
+    let literal = match tokens.next() {
+        Some(TokenTree::Literal(path)) if is_str_literal(&path) => path,
+        Some(other) => return Err(Error::new("expected a string literal", other.span())),
+        None => return Err(Error::new("expected a string literal", Span::call_site())),
+    };
+
+    match tokens.next() {
+        None => {}
+        Some(TokenTree::Punct(comma)) if comma.as_char() == ',' => {}
+        Some(other) => return Err(Error::new("expected a single path", other.span())),
+    }
+
+    match tokens.next() {
+        None => Ok(literal),
+        Some(other) => Err(Error::new("expected a single path", other.span())),
+    }
+}
+
+/// Check that the path exists in a development build, and return it.
+///
+/// In a crate archive the data is only available once it was fetched for the tests, we can not
+/// check it when compiling.
+fn check_path(literal: &Literal) -> Result<String, Error> {
+    let value = str_value(literal)?;
+    let path = Path::new(&value);
+    if path.has_root() || path.is_absolute() {
+        return Err(Error::new(
+            "test data must be relative to the manifest",
+            literal.span(),
+        ));
+    }
+
+    let manifest = match std::env::var_os("CARGO_MANIFEST_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => return Ok(value),
+    };
+
+    let in_crate = std::env::var_os("CARGO_XTEST_VCS_INFO").is_some()
+        || manifest.join(".cargo_vcs_info.json").exists();
+    if in_crate {
+        return Ok(value);
+    }
+
+    if !manifest.join(path).exists() {
+        return Err(Error::new(
+            format!(
+                "test data does not exist: {}",
+                manifest.join(path).display()
+            ),
+            literal.span(),
+        ));
+    }
+
+    Ok(value)
+}
+
+/// The value of a string literal, for those that can name a path.
+fn str_value(literal: &Literal) -> Result<String, Error> {
+    let repr = literal.to_string();
+    if let Some(raw) = repr.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        return Ok(raw[hashes + 1..raw.len() - hashes - 1].to_owned());
+    }
+
+    let inner = repr
+        .strip_prefix('"')
+        .and_then(|repr| repr.strip_suffix('"'))
+        .ok_or_else(|| Error::new("expected a string literal", literal.span()))?;
+
+    let mut value = String::new();
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            value.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some(escaped @ ('\\' | '"' | '\'')) => value.push(escaped),
+            _ => {
+                return Err(Error::new(
+                    "only the escapes `\\\\`, `\\\"` and `\\'` are supported in a path",
+                    literal.span(),
+                ))
+            }
+        }
+    }
+
+    Ok(value)
+}
+
+fn is_str_literal(lit: &Literal) -> bool {
+    let repr = lit.to_string();
+    repr.starts_with('"') || repr.starts_with("r\"") || repr.starts_with("r#")
+}
+
+impl TestFn {
+    fn parse(item: TokenStream) -> Result<Self, Error> {
+        let tokens: Vec<TokenTree> = item.into_iter().collect();
+
+        let mut attrs = vec![];
+        let mut has_test_attr = false;
+        let mut idx = 0;
+        while let (Some(TokenTree::Punct(pound)), Some(TokenTree::Group(group))) =
+            (tokens.get(idx), tokens.get(idx + 1))
+        {
+            if pound.as_char() != '#' || group.delimiter() != Delimiter::Bracket {
+                break;
+            }
+
+            has_test_attr |= is_test_attr(group.stream());
+            attrs.extend_from_slice(&tokens[idx..idx + 2]);
+            idx += 2;
+        }
+
+        let item = tokens[idx..].to_vec();
+        let fn_idx = item
+            .iter()
+            .position(|tt| matches!(tt, TokenTree::Ident(ident) if ident.to_string() == "fn"))
+            .ok_or_else(|| Error::new("expected a function", Span::call_site()))?;
+
+        let name = match item.get(fn_idx + 1) {
+            Some(TokenTree::Ident(name)) => name.clone(),
+            _ => return Err(Error::new("expected a function name", item[fn_idx].span())),
+        };
+
+        let params = fn_idx + 2;
+        match item.get(params) {
+            Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => {}
+            Some(other) => {
+                return Err(Error::new(
+                    "generic test functions are not supported",
+                    other.span(),
+                ))
+            }
+            None => return Err(Error::new("expected function parameters", name.span())),
+        }
+
+        let body = match item.last() {
+            Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => {
+                item.len() - 1
+            }
+            _ => return Err(Error::new("expected a function body", name.span())),
+        };
+
+        let output = item[params + 1..body].to_vec();
+
+        Ok(TestFn {
+            attrs,
+            has_test_attr,
+            name,
+            output,
+            item,
+        })
+    }
+
+    fn expand(self, data: &[Literal]) -> TokenStream {
+        let mut setup = String::from(
+            "#[allow(unused_mut)] let mut __xtest_data_setup = ::xtest_data::setup!();\n",
+        );
+        setup.push_str(&format!(
+            "let __xtest_data_files: [::xtest_data::Files; {}] = [",
+            data.len()
+        ));
+        for path in data {
+            setup.push_str(&format!("__xtest_data_setup.add({}),", path));
+        }
+        setup.push_str("];\n");
+        setup.push_str("let __xtest_data = __xtest_data_setup.build();\n");
+
+        let mut call = vec![];
+        for idx in 0..data.len() {
+            call.push(format!("__xtest_data.path(&__xtest_data_files[{}])", idx));
+        }
+
+        let mut body: TokenStream = self.item.into_iter().collect();
+        body.extend(setup.parse::<TokenStream>().unwrap());
+        body.extend([TokenTree::Ident(self.name.clone())]);
+        body.extend([TokenTree::Group(Group::new(
+            Delimiter::Parenthesis,
+            call.join(", ").parse().unwrap(),
+        ))]);
+
+        let mut out = TokenStream::new();
+        if !self.has_test_attr {
+            out.extend("#[test]".parse::<TokenStream>().unwrap());
+        }
+        out.extend(self.attrs);
+        out.extend([
+            TokenTree::Ident(Ident::new("fn", Span::call_site())),
+            TokenTree::Ident(self.name),
+            TokenTree::Group(Group::new(Delimiter::Parenthesis, TokenStream::new())),
+        ]);
+        out.extend(self.output);
+        out.extend([TokenTree::Group(Group::new(Delimiter::Brace, body))]);
+        out
+    }
+}
+
+impl Error {
+    fn new(message: impl Into<String>, span: Span) -> Self {
+        Error {
+            message: message.into(),
+            span,
+        }
+    }
+
+    fn into_compile_error(self) -> TokenStream {
+        let mut message = Literal::string(&self.message);
+        message.set_span(self.span);
+
+        let mut bang = Punct::new('!', Spacing::Alone);
+        bang.set_span(self.span);
+
+        vec![
+            TokenTree::Punct(Punct::new(':', Spacing::Joint)),
+            TokenTree::Punct(Punct::new(':', Spacing::Alone)),
+            TokenTree::Ident(Ident::new("core", self.span)),
+            TokenTree::Punct(Punct::new(':', Spacing::Joint)),
+            TokenTree::Punct(Punct::new(':', Spacing::Alone)),
+            TokenTree::Ident(Ident::new("compile_error", self.span)),
+            TokenTree::Punct(bang),
+            TokenTree::Group(Group::new(
+                Delimiter::Parenthesis,
+                TokenTree::Literal(message).into(),
+            )),
+            TokenTree::Punct(Punct::new(';', Spacing::Alone)),
+        ]
+        .into_iter()
+        .collect()
+    }
+}
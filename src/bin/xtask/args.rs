@@ -70,6 +70,12 @@ pub enum XtaskCommand {
         /// within the tree given by the current VCS (otherwise it wouldn't be part of the pack).
         #[arg(long, default_value = "false")]
         allow_dirty: bool,
+        /// Only compile the tests, and pack the paths declared with `xtest_data::path!`.
+        ///
+        /// The compiler records these paths in the target directory. Data that the tests only
+        /// register at runtime is not packed.
+        #[arg(long, default_value = "false")]
+        no_run: bool,
     },
     /// Run the data preparation step of a crate artifact.
     ///
@@ -82,6 +88,26 @@ pub enum XtaskCommand {
     /// in stderr.
     #[command(alias = "fetch-artifacts")]
     Fetch {
+        /// The path to the source crate archive, or an unpacked version.
+        path: PathBuf,
+        /// Provide a downloaded `pack-artifact`.
+        #[arg(id = "pack-artifact", long)]
+        pack_artifact: Option<PathBuf>,
+        /// Provide an explicit write location. Otherwise, a default is chosen based on the crate
+        /// name, version, and target directory.
+        output: Option<PathBuf>,
+    },
+    /// Check out the test data of a crate archive on the host.
+    ///
+    /// Downloads the artifacts if necessary, unpacks them and checks out all contained files of
+    /// the commit recorded in the crate into a directory. The output directory is suitable for use
+    /// as a `CARGO_XTEST_DATA_TREE` variable, in which the library does not run `git` at all. Use
+    /// this when the tests run under an emulator, a `CARGO_TARGET_*_RUNNER` or Miri.
+    ///
+    /// Writes bash-formatted environment variables to stdout. No other output is produced except
+    /// in stderr.
+    #[command(alias = "materialise")]
+    Materialize {
         /// The path to the source crate archive.
         path: PathBuf,
         /// Provide a downloaded `pack-artifact`.
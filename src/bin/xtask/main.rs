@@ -6,7 +6,7 @@ mod util;
 use self::args::{CargoXtestData, XtaskCommand};
 use self::util::{anchor_error, as_io_error, undiagnosed_io_error, LocatedError};
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{env, fs};
 
 use clap::Parser;
@@ -31,7 +31,8 @@ fn main() -> Result<(), LocatedError> {
             let unpacked = task::artifacts::unpack(&packed, &target, &tmp)?;
 
             let test =
-                task::test::test(&package.crate_, &target, &unpacked, &package.vcs_info, &tmp)?;
+                task::test::test(&package.crate_, &target, &unpacked, &package.vcs_info, &tmp)
+                    .map_err(|err| keep_for_failure(&mut private_tempdir, err))?;
 
             let output = task::output::write_artifacts(&source, &target, &packed)?;
             eprintln!("Test success: {:?}", test);
@@ -40,8 +41,14 @@ fn main() -> Result<(), LocatedError> {
             println!("{}", output.display());
             Ok(())
         }
-        XtaskCommand::Pack { path, allow_dirty } => {
-            let source = target::LocalSource::with_simple_repository(&path).with_dirty(allow_dirty);
+        XtaskCommand::Pack {
+            path,
+            allow_dirty,
+            no_run,
+        } => {
+            let source = target::LocalSource::with_simple_repository(&path)
+                .with_dirty(allow_dirty)
+                .with_no_run(no_run);
             let target = target::Target::from_dir(&source)?;
 
             let tmp = mk_tmpdir(&mut private_tempdir, &target);
@@ -82,7 +89,8 @@ fn main() -> Result<(), LocatedError> {
             let unpack = task::artifacts::unpack(&archive, &target, &tmp)?;
 
             let test =
-                task::test::test(&source, &target, &unpack, &target::VcsInfo::FromCrate, &tmp)?;
+                task::test::test(&source, &target, &unpack, &target::VcsInfo::FromCrate, &tmp)
+                    .map_err(|err| keep_for_failure(&mut private_tempdir, err))?;
 
             eprintln!("Test successful: {:?}", test);
             Ok(())
@@ -139,6 +147,43 @@ fn main() -> Result<(), LocatedError> {
                 location.display()
             );
 
+            Ok(())
+        }
+        XtaskCommand::Materialize {
+            path,
+            pack_artifact,
+            output,
+        } => {
+            let source = target::CrateSource {
+                path: path.to_owned(),
+            };
+
+            let target = target::Target::from_crate(&source)?;
+            let commit = target::Target::commit_from_crate(&source)?;
+            let tmp = mk_tmpdir(&mut private_tempdir, &target);
+
+            let archive = match pack_artifact {
+                None => {
+                    let download = task::dl::download(&target, &tmp)?;
+                    download.artifact
+                }
+                Some(artifact) => task::artifacts::PackedArtifacts {
+                    path: artifact.to_owned(),
+                },
+            };
+
+            let location = match output {
+                Some(location) => location,
+                None => Path::new("target/xtest-data-tree").join(target.expected_dir_name()),
+            };
+
+            let unpack = task::artifacts::unpack(&archive, &target, &tmp)?;
+            let tree = task::materialize::materialize(&unpack, &commit, &location, &tmp)?;
+            let location = std::fs::canonicalize(&tree.path).map_err(anchor_error())?;
+
+            eprintln!("Environment variables to set:");
+            println!("export CARGO_XTEST_DATA_TREE=\"{}\"", location.display());
+
             Ok(())
         }
     }
@@ -169,3 +214,18 @@ members = ["{}"]
         PathBuf::from,
     )
 }
+
+/// Keep our temporary directory when the test of the crate failed.
+///
+/// The failed test points into it, to the crate, its pack objects and the checkout of its data.
+fn keep_for_failure(private_tempdir: &mut Option<TempDir>, err: LocatedError) -> LocatedError {
+    if let Some(temp) = private_tempdir.take() {
+        let temp = core::mem::ManuallyDrop::new(temp);
+        eprintln!(
+            "The crate and its test data are kept in {}",
+            temp.path().display()
+        );
+    }
+
+    err
+}
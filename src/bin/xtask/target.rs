@@ -16,6 +16,8 @@ pub struct LocalSource {
     pub cargo: PathBuf,
     /// Allow this source tree to be dirty? May be best-effort.
     pub dirty: bool,
+    /// Pack the paths declared with `xtest_data::path!` instead of running the tests.
+    pub no_run: bool,
 }
 
 /// A local path to a `.crate` archive.
@@ -67,6 +69,7 @@ impl LocalSource {
         LocalSource {
             cargo: path.join("Cargo.toml"),
             dirty: false,
+            no_run: false,
         }
     }
 
@@ -74,12 +77,23 @@ impl LocalSource {
         LocalSource { dirty, ..self }
     }
 
+    pub fn with_no_run(self, no_run: bool) -> Self {
+        LocalSource { no_run, ..self }
+    }
+
     pub fn target_directory(&self, _: &Target) -> PathBuf {
         // FIXME: use metadata for actual target directory.
         self.cargo.parent().unwrap().join("target/xtest-data")
     }
 }
 
+impl TargetStatic {
+    /// The `package.repository` of the manifest, if any.
+    pub fn repository(&self) -> Option<&str> {
+        self.extra.get("repository").and_then(Value::as_str)
+    }
+}
+
 impl Target {
     pub(crate) fn from_dir(spec: &LocalSource) -> Result<Self, LocatedError> {
         let toml = std::fs::read(&spec.cargo).map_err(anchor_error())?;
@@ -87,6 +101,12 @@ impl Target {
     }
 
     pub(crate) fn from_crate(archive: &CrateSource) -> Result<Self, LocatedError> {
+        // An unpacked crate, such as the one in which the library found no pack objects.
+        if archive.path.is_dir() {
+            let toml = std::fs::read(archive.path.join("Cargo.toml")).map_err(anchor_error())?;
+            return Self::from_toml(&toml);
+        }
+
         let crate_tar = Command::new("gunzip")
             .arg("-c")
             .arg(&archive.path)
@@ -103,6 +123,60 @@ impl Target {
         Self::from_toml(&toml.stdout)
     }
 
+    /// Read the commit ID recorded by cargo in the `.cargo_vcs_info.json` of a crate archive.
+    pub(crate) fn commit_from_crate(archive: &CrateSource) -> Result<String, LocatedError> {
+        // An unpacked crate, as in `from_crate`.
+        if archive.path.is_dir() {
+            let vcs_info =
+                std::fs::read(archive.path.join(".cargo_vcs_info.json")).map_err(anchor_error())?;
+            return Self::commit_from_vcs_info(&vcs_info);
+        }
+
+        let crate_tar = Command::new("gunzip")
+            .arg("-c")
+            .arg(&archive.path)
+            .output()
+            .map_err(anchor_error())?
+            .stdout;
+
+        let vcs_info = Command::new("tar")
+            .arg("-O")
+            .args([
+                "--extract",
+                "--file",
+                "-",
+                "--wildcards",
+                "*/.cargo_vcs_info.json",
+            ])
+            .input_output(&crate_tar)
+            .map_err(anchor_error())?;
+
+        Self::commit_from_vcs_info(&vcs_info.stdout)
+    }
+
+    fn commit_from_vcs_info(vcs_info: &[u8]) -> Result<String, LocatedError> {
+        let vcs_info = core::str::from_utf8(vcs_info).map_err(anchor_error())?;
+        let vcs_info: tinyjson::JsonValue = vcs_info
+            .parse()
+            .map_err(as_io_error)
+            .map_err(anchor_error())?;
+
+        let key = |value: &tinyjson::JsonValue, key: &str| {
+            value
+                .get::<HashMap<String, tinyjson::JsonValue>>()
+                .and_then(|table| table.get(key))
+                .cloned()
+        };
+
+        let commit = key(&vcs_info, "git")
+            .and_then(|git| key(&git, "sha1"))
+            .and_then(|sha1| sha1.get::<String>().cloned())
+            .ok_or_else(undiagnosed_io_error())
+            .map_err(anchor_error())?;
+
+        Ok(commit.trim().to_owned())
+    }
+
     pub(crate) fn from_toml(toml: &[u8]) -> Result<Self, LocatedError> {
         let toml = core::str::from_utf8(toml).map_err(anchor_error())?;
 
@@ -2,6 +2,8 @@
 pub mod artifacts;
 /// Based on a target spec, prepare the pack archive.
 pub mod dl;
+/// Check out the data of a crate archive, for test runs without `git`.
+pub mod materialize;
 /// Create non-temporary files.
 pub mod output;
 /// A `cargo package` that runs all relevant tests, and adds vcs_info_data when dirty.
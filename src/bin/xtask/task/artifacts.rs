@@ -46,6 +46,8 @@ pub fn pack(
     // Invert: tar -C /tmp --extract --file -
     let create_tar = Command::new("tar")
         .args(["--create", "--file", "-"])
+        // Stamps only remember which packs were already written, they are not published.
+        .arg("--exclude=*.stamp")
         .args(["--xform", "s//target\\/xtest-data\\//"])
         .arg("-C")
         .arg(&data.path)
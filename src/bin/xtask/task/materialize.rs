@@ -0,0 +1,150 @@
+//! Check out all files contained in pack objects, for test runs that can not use `git`.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tinyjson::JsonValue;
+
+use crate::util::{anchor_error, as_io_error, GoodOutput, LocatedError};
+
+use super::artifacts::UnpackedArchive;
+
+/// Name of the mapping file in the root of the tree, as expected by the library.
+const MAPPING: &str = "xtest-data-tree.json";
+
+const GIT: &str = "git";
+
+#[derive(Debug)]
+pub struct MaterializedTree {
+    /// Path to the checked out data, suitable as `CARGO_XTEST_DATA_TREE`.
+    pub path: PathBuf,
+}
+
+pub fn materialize(
+    objects: &UnpackedArchive,
+    commit: &str,
+    output: &Path,
+    tmp: &Path,
+) -> Result<MaterializedTree, LocatedError> {
+    let repo = tmp.join("xtest-data-materialize");
+    let _ = fs::remove_dir_all(&repo);
+
+    Command::new(GIT)
+        .args(["init", "--bare", "--quiet"])
+        .arg(&repo)
+        .success()
+        .map_err(anchor_error())?;
+    // We only have a subset of history, like the library's clone.
+    fs::write(repo.join("shallow"), format!("{}\n", commit)).map_err(anchor_error())?;
+
+    // Same lookup as the library: packs keyed by commit, or all packs in the directory.
+    let keyed = objects.path.join(commit);
+    let packs = if keyed.is_dir() {
+        &keyed
+    } else {
+        &objects.path
+    };
+
+    for entry in fs::read_dir(packs).map_err(anchor_error())? {
+        let path = entry.map_err(anchor_error())?.path();
+        if path.extension().map_or(true, |ext| ext != "pack") {
+            continue;
+        }
+
+        let pack = fs::read(&path).map_err(anchor_error())?;
+        git(&repo)
+            .args(["unpack-objects", "-r"])
+            .input_output(&pack)
+            .map_err(anchor_error())?;
+    }
+
+    // All blobs in the commit. Trees are always packed, blobs only for the requested paths.
+    let listing = git(&repo)
+        .args(["ls-tree", "-r", "-z", "--full-tree"])
+        .arg(commit)
+        .input_output(&[])
+        .map_err(anchor_error())?
+        .stdout;
+    let listing = String::from_utf8(listing)
+        .map_err(as_io_error)
+        .map_err(anchor_error())?;
+
+    let blobs: Vec<(&str, &str)> = listing
+        .split_terminator('\0')
+        .filter_map(|entry| {
+            let (info, path) = entry.split_once('\t')?;
+            let mut info = info.split(' ');
+            let (_mode, kind, oid) = (info.next()?, info.next()?, info.next()?);
+            Some((oid, path)).filter(|_| kind == "blob")
+        })
+        .collect();
+
+    let query: String = blobs.iter().map(|(oid, _)| format!("{}\n", oid)).collect();
+    let present = git(&repo)
+        .args(["cat-file", "--batch-check=%(objectname)"])
+        .input_output(&query)
+        .map_err(anchor_error())?
+        .stdout;
+    let present = String::from_utf8(present)
+        .map_err(as_io_error)
+        .map_err(anchor_error())?;
+    // Missing objects are reported as `<oid> missing` instead.
+    let present: HashSet<&str> = present.lines().filter(|line| !line.contains(' ')).collect();
+
+    let files: Vec<&str> = blobs
+        .iter()
+        .filter(|(oid, _)| present.contains(oid))
+        .map(|(_, path)| *path)
+        .collect();
+
+    let _ = fs::remove_dir_all(output);
+    fs::create_dir_all(output).map_err(anchor_error())?;
+
+    if !files.is_empty() {
+        let pathspecs: String = files
+            .iter()
+            .map(|path| format!(":(top,literal){}\0", path))
+            .collect();
+
+        git(&repo)
+            .arg("--work-tree")
+            .arg(output)
+            .args(["checkout", "--no-guess", "--force"])
+            .args(["--pathspec-from-file=-", "--pathspec-file-nul"])
+            .arg(commit)
+            .input_output(&pathspecs)
+            .map_err(anchor_error())?;
+    }
+
+    let mapping = JsonValue::from({
+        let mut map = HashMap::new();
+        map.insert("commit".to_string(), JsonValue::from(commit.to_string()));
+        map.insert(
+            "files".to_string(),
+            JsonValue::from(
+                files
+                    .iter()
+                    .map(|path| JsonValue::from(path.to_string()))
+                    .collect::<Vec<_>>(),
+            ),
+        );
+        map
+    });
+
+    let mapping = mapping
+        .stringify()
+        .map_err(as_io_error)
+        .map_err(anchor_error())?;
+    fs::write(output.join(MAPPING), mapping).map_err(anchor_error())?;
+
+    Ok(MaterializedTree {
+        path: output.to_owned(),
+    })
+}
+
+fn git(repo: &Path) -> Command {
+    let mut cmd = Command::new(GIT);
+    cmd.arg("--git-dir").arg(repo);
+    cmd
+}
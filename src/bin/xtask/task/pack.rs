@@ -3,8 +3,10 @@ use crate::target::{CrateSource, LocalSource, Target, VcsInfo};
 use crate::util::{anchor_error, as_io_error, GoodOutput, LocatedError};
 use crate::CARGO;
 
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use tinyjson::JsonValue;
 
 use super::artifacts::UnpackedArchive;
 
@@ -22,6 +24,7 @@ pub(crate) fn pack(
     tmp: &Path,
 ) -> Result<PackedData, LocatedError> {
     let filename = target.expected_crate_name();
+    let (dirty, no_run) = (repo.dirty, repo.no_run);
     let repo = repo
         .cargo
         .parent()
@@ -53,11 +56,16 @@ pub(crate) fn pack(
 
     let packdir = repo.join("target").join("xtest-data");
 
-    Command::new(CARGO)
-        .args(["test"])
-        .env("CARGO_XTEST_DATA_PACK_OBJECTS", &packdir)
-        .success()
-        .map_err(anchor_error())?;
+    if no_run {
+        pack_declared(&repo, target, &packdir, dirty)?;
+    } else {
+        Command::new(CARGO)
+            .args(["test"])
+            .env("CARGO_XTEST_DATA_PACK_OBJECTS", &packdir)
+            .envs(dirty.then_some(("CARGO_XTEST_DATA_ALLOW_DIRTY", "1")))
+            .success()
+            .map_err(anchor_error())?;
+    }
 
     Command::new(CARGO)
         .args(["package", "--allow-dirty", "--no-verify"])
@@ -80,3 +88,123 @@ pub(crate) fn pack(
         crate_: CrateSource { path: crate_path },
     })
 }
+
+/// Pack the paths declared with `xtest_data::path!`, as found in the compiled tests.
+fn pack_declared(
+    repo: &Path,
+    target: &Target,
+    packdir: &Path,
+    dirty: bool,
+) -> Result<(), LocatedError> {
+    let mut listed: Vec<String> = vec![];
+    for executable in compile_tests(repo)? {
+        let compiled = std::fs::read(&executable).map_err(anchor_error())?;
+        for path in declared_paths(&compiled) {
+            if !listed.iter().any(|known| *known == path) {
+                listed.push(path);
+            }
+        }
+    }
+
+    if listed.is_empty() {
+        return Err(as_io_error(
+            "No paths were declared with `xtest_data::path!` in the tests",
+        ))
+        .map_err(anchor_error());
+    }
+
+    let mut setup = xtest_data::Setup::builder()
+        .package(&target.env.name)
+        .manifest_dir(repo)
+        .pack_objects(packdir);
+    if let Some(repository) = target.env.repository() {
+        setup = setup.repository(repository);
+    }
+    if dirty {
+        setup = setup.allow_dirty(true);
+    }
+
+    let mut setup = setup
+        .try_build()
+        .map_err(as_io_error)
+        .map_err(anchor_error())?;
+    for path in &listed {
+        setup.add(path);
+    }
+
+    setup
+        .try_build()
+        .map(drop)
+        .map_err(as_io_error)
+        .map_err(anchor_error())
+}
+
+/// Compile the tests of the package, returning their executables.
+fn compile_tests(repo: &Path) -> Result<Vec<PathBuf>, LocatedError> {
+    let mut cmd = Command::new(CARGO);
+    cmd.args(["test", "--no-run", "--message-format=json"])
+        .current_dir(repo)
+        .stderr(std::process::Stdio::inherit());
+    // Fails on an unsuccessful exit, unlike the inherent method of the same name.
+    let messages = GoodOutput::output(&mut cmd).map_err(anchor_error())?.stdout;
+    let messages = std::str::from_utf8(&messages)
+        .map_err(as_io_error)
+        .map_err(anchor_error())?;
+
+    let manifest = repo.join("Cargo.toml");
+    let mut executables = vec![];
+    for message in messages.lines() {
+        let message: JsonValue = message
+            .parse()
+            .map_err(as_io_error)
+            .map_err(anchor_error())?;
+
+        let artifact: &HashMap<String, JsonValue> = match message.get() {
+            Some(artifact) => artifact,
+            None => continue,
+        };
+
+        // Only the tests of this package, not those of other members of its workspace.
+        let ours = match artifact.get("manifest_path") {
+            Some(JsonValue::String(path)) => Path::new(path) == manifest,
+            _ => false,
+        };
+
+        if let (true, Some(JsonValue::String(executable))) = (ours, artifact.get("executable")) {
+            executables.push(PathBuf::from(executable));
+        }
+    }
+
+    Ok(executables)
+}
+
+/// The paths that `xtest_data::path!` left in compiled code.
+///
+/// Each is preceded by a marker and ends with a NUL byte.
+fn declared_paths(compiled: &[u8]) -> Vec<String> {
+    const MARKER: &[u8] = b"\0xtest-data-path:";
+
+    let mut paths = vec![];
+    let mut rest = compiled;
+    while let Some(start) = find(rest, MARKER) {
+        rest = &rest[start + MARKER.len()..];
+        let end = match rest.iter().position(|&byte| byte == 0) {
+            Some(end) => end,
+            None => break,
+        };
+
+        if let Ok(path) = std::str::from_utf8(&rest[..end]) {
+            paths.push(path.to_owned());
+        }
+
+        rest = &rest[end..];
+    }
+
+    paths
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
@@ -21,21 +21,31 @@ pub fn test(
     // Try to remove it but ignore failure.
     let _ = std::fs::remove_dir_all(&extracted).map_err(anchor_error());
 
-    // gunzip -c target/package/xtest-data-0.0.2.crate
-    let crate_tar = Command::new("gunzip")
-        .arg("-c")
-        .arg(&crate_.path)
-        .output()
-        .map_err(anchor_error())?
-        .stdout;
+    if crate_.path.is_dir() {
+        // An unpacked crate is tested in a copy, as an archive would be.
+        Command::new("cp")
+            .arg("-R")
+            .arg(&crate_.path)
+            .arg(&extracted)
+            .success()
+            .map_err(anchor_error())?;
+    } else {
+        // gunzip -c target/package/xtest-data-0.0.2.crate
+        let crate_tar = Command::new("gunzip")
+            .arg("-c")
+            .arg(&crate_.path)
+            .output()
+            .map_err(anchor_error())?
+            .stdout;
 
-    // tar -C /tmp --extract --file -
-    Command::new("tar")
-        .arg("-C")
-        .arg(&tmp)
-        .args(["--extract", "--file", "-"])
-        .input_output(&crate_tar)
-        .map_err(anchor_error())?;
+        // tar -C /tmp --extract --file -
+        Command::new("tar")
+            .arg("-C")
+            .arg(&tmp)
+            .args(["--extract", "--file", "-"])
+            .input_output(&crate_tar)
+            .map_err(anchor_error())?;
+    }
 
     // TMPDIR=/tmp CARGO_XTEST_DATA_FETCH=1 cargo test  -- --nocapture
     Command::new(CARGO)
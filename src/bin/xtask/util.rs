@@ -81,13 +81,18 @@ impl GoodOutput for Command {
         self.stdin(Stdio::piped());
         self.stdout(Stdio::piped());
         let mut child = self.spawn()?;
-        let output = {
+        let inp: &[u8] = inp.as_ref();
+        let output = std::thread::scope(|scope| {
             let mut stdin = child.stdin.take().unwrap();
-            std::io::Write::write(&mut stdin, inp.as_ref())?;
-            // Terminate the input here.
-            drop(stdin);
-            child.wait_with_output()?
-        };
+            // Write concurrently, the child may fill its output pipe before consuming all input.
+            let writer = scope.spawn(move || {
+                // Dropping `stdin` terminates the input.
+                std::io::Write::write_all(&mut stdin, inp)
+            });
+            let output = child.wait_with_output()?;
+            writer.join().expect("Writing to a pipe does not panic")?;
+            Ok::<_, io::Error>(output)
+        })?;
         if !output.status.success() {
             return Err(io::ErrorKind::Other.into());
         }
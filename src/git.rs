@@ -1,8 +1,9 @@
 use std::ffi::{OsStr, OsString};
-use std::path::{Path, PathBuf};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
 use std::process::{Command, Stdio};
 
-use crate::inconclusive;
+use crate::{inconclusive, lfs, log};
 
 /// How we access `git` repositories.
 #[derive(Debug)]
@@ -11,15 +12,24 @@ pub(crate) struct Git {
 }
 
 /// A bare repository created by us.
+#[derive(Debug)]
 pub(crate) struct ShallowBareRepository {
     path: PathBuf,
 }
 
 /// The repository containing the manifest of the crate to integration test.
+#[derive(Debug)]
 pub(crate) struct CrateDir {
     path: PathBuf,
 }
 
+/// The files of a git directory, which we read for information that does not need git itself.
+struct GitFiles {
+    git_dir: PathBuf,
+    /// Shared by all worktrees, the same as `git_dir` in the main worktree.
+    common_dir: PathBuf,
+}
+
 pub(crate) struct FileWaitLock {
     lock: std::fs::File,
 }
@@ -30,11 +40,57 @@ pub(crate) struct Origin {
 
 /// A git commit ID.
 /// This is treated as opaque string data. Usually it's a Sha1 hash (20 byte, hex-encoded).
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct CommitId(String);
 
+/// A submodule, as recorded by a gitlink in the tree of its superproject.
+#[derive(Debug, Clone)]
+pub(crate) struct Submodule {
+    /// The path of the submodule, relative to the root of the superproject.
+    pub path: PathBuf,
+    /// The commit of the submodule that the superproject pins.
+    pub commit: CommitId,
+}
+
+impl Submodule {
+    /// The part of `path` within this submodule.
+    ///
+    /// A path that contains the submodule refers to all of it, which is the empty path. Returns
+    /// `None` if the path is unrelated to the submodule.
+    pub fn relative<'p>(&self, path: &'p Path) -> Option<&'p Path> {
+        if let Ok(relative) = path.strip_prefix(&self.path) {
+            Some(relative)
+        } else if self.path.starts_with(path) {
+            Some(Path::new(""))
+        } else {
+            None
+        }
+    }
+}
+
+/// A tracked file whose state differs from the committed one.
+#[derive(Debug)]
+pub(crate) struct DirtyFile {
+    pub kind: Dirty,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Dirty {
+    /// The working tree differs from the index.
+    Modified,
+    /// The index differs from `HEAD`, i.e. there are staged changes.
+    Staged,
+    /// The file was deleted, in the working tree or the index.
+    Deleted,
+    /// The file has unresolved merge conflicts.
+    Unmerged,
+}
+
 pub(crate) enum PathSpec<'lt> {
     Path(&'lt Path),
+    /// Leave out a path below the other paths, it applies to all of them.
+    Exclude(&'lt Path),
 }
 
 impl Git {
@@ -42,6 +98,63 @@ impl Git {
         which::which("git").map(|bin| Git { bin })
     }
 
+    /// Check if `dir` is within the worktree of a repository.
+    pub fn is_work_tree(&self, dir: &Path) -> bool {
+        let mut cmd = Command::new(&self.bin);
+        cmd.current_dir(dir);
+        cmd.args(["rev-parse", "--is-inside-work-tree"]);
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        match log::output(&mut cmd) {
+            Ok(exit) => exit.status.success() && exit.stdout.starts_with(b"true"),
+            Err(_) => false,
+        }
+    }
+
+    /// The path of `dir` relative to the root of its worktree, empty at the root itself.
+    pub fn show_prefix(&self, dir: &Path) -> PathBuf {
+        let mut cmd = Command::new(&self.bin);
+        cmd.current_dir(dir);
+        cmd.args(["rev-parse", "--show-prefix"]);
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+        if !exit.status.success() {
+            let stderr = String::from_utf8_lossy(&exit.stderr);
+            log::info(format_args!(
+                "git rev-parse --show-prefix: {}",
+                stderr.trim_end()
+            ));
+            inconclusive(&mut format!("Not a git repository: {}", dir.display()));
+        }
+
+        let prefix =
+            String::from_utf8(exit.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+        PathBuf::from(prefix.trim_end())
+    }
+
+    /// The directory with objects and other data shared by all worktrees of a repository.
+    pub fn common_dir(&self, repo: &Path) -> PathBuf {
+        let mut cmd = Command::new(&self.bin);
+        cmd.current_dir(repo);
+        cmd.args(["rev-parse", "--git-common-dir"]);
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+        if !exit.status.success() {
+            eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
+            inconclusive(&mut format!("Not a git repository: {}", repo.display()));
+        }
+
+        let common =
+            String::from_utf8(exit.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+        // May be relative to the directory in which we ran.
+        repo.join(common.trim_end())
+    }
+
     /// Prepare `path` as a shallow clone of `origin`.
     /// Aborts if this isn't possible (see error handling policy).
     pub fn bare(&self, path: PathBuf, head: &CommitId) -> ShallowBareRepository {
@@ -58,8 +171,7 @@ impl Git {
             cmd.args(["symbolic-ref", "HEAD"]);
         }
 
-        cmd.status()
-            .unwrap_or_else(|mut err| inconclusive(&mut err));
+        log::status(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
 
         let content = format!("{}\n", head.0);
         std::fs::write(repo.path.join("shallow"), content)
@@ -69,6 +181,12 @@ impl Git {
     }
 }
 
+impl CommitId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
 impl From<&'_ str> for CommitId {
     fn from(st: &'_ str) -> CommitId {
         let st = st.trim();
@@ -81,17 +199,59 @@ impl From<&'_ str> for CommitId {
     }
 }
 
-impl CrateDir {
-    pub fn new(path: &str, git: &Git) -> Self {
-        let dir = CrateDir {
-            path: Path::new(path).to_owned(),
+impl DirtyFile {
+    /// Classify one entry of `git status --porcelain=v2 -z`.
+    ///
+    /// Ordinary entries are `1 XY sub mH mI mW hH hI path` where `X` is the state of the index
+    /// and `Y` the state of the working tree. Unmerged entries start with `u` instead, and have a
+    /// few more fields before the path. Renames (`2`) are disabled by `--no-renames`.
+    fn from_porcelain_v2(item: &str) -> Option<Self> {
+        let (fields, unmerged) = match item.as_bytes().first()? {
+            b'1' => (8, false),
+            b'u' => (10, true),
+            _ => return None,
         };
 
-        let mut cmd = dir.exec(git);
-        cmd.args(["status", "--short"]);
-        cmd.status()
-            .unwrap_or_else(|mut err| inconclusive(&mut err));
-        dir
+        let mut parts = item.splitn(fields + 1, ' ');
+        let xy = parts.nth(1)?.as_bytes();
+        let path = parts.last()?.to_owned();
+        let (index, worktree) = (*xy.first()?, *xy.get(1)?);
+
+        let kind = if unmerged {
+            Dirty::Unmerged
+        } else if index == b'D' || worktree == b'D' {
+            Dirty::Deleted
+        } else if worktree != b'.' {
+            Dirty::Modified
+        } else if index != b'.' {
+            Dirty::Staged
+        } else {
+            return None;
+        };
+
+        Some(DirtyFile { kind, path })
+    }
+}
+
+impl core::fmt::Display for DirtyFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let kind = match self.kind {
+            Dirty::Modified => "modified",
+            Dirty::Staged => "staged",
+            Dirty::Deleted => "deleted",
+            Dirty::Unmerged => "unmerged",
+        };
+
+        write!(f, "{}:\t{}", kind, self.path)
+    }
+}
+
+impl CrateDir {
+    /// The worktree of `path`, which the caller found with [`Git::is_work_tree`].
+    pub fn new(path: &Path) -> Self {
+        CrateDir {
+            path: path.to_owned(),
+        }
     }
 
     pub fn exec(&self, git: &Git) -> Command {
@@ -100,37 +260,163 @@ impl CrateDir {
         // Ensure we open _no_ handles.
         // Override this later if necessary.
         cmd.stdout(Stdio::null());
-        cmd.stderr(Stdio::null());
+        cmd.stderr(Stdio::piped());
         cmd
     }
 
-    pub fn tracked(&self, git: &Git, paths: &mut dyn Iterator<Item = PathSpec<'_>>) {
+    /// The commit currently checked out.
+    pub fn head(&self, git: &Git) -> CommitId {
+        if let Some(head) = self.git_files().and_then(|files| files.head()) {
+            return head;
+        }
+
         let mut cmd = self.exec(git);
+        cmd.args(["rev-parse", "--verify", "HEAD"]);
         cmd.stdout(Stdio::piped());
+
+        let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+        if !exit.status.success() {
+            inconclusive(&mut "Git operation was not successful");
+        }
+
+        let head = String::from_utf8(exit.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+        CommitId::from(head.as_str())
+    }
+
+    /// The id of the blob or tree at `path` in `rev`, if it exists.
+    pub fn object_id(&self, git: &Git, rev: &str, path: &Path) -> Option<String> {
+        object_id(|| self.exec(git), rev, path)
+    }
+
+    /// The index of the worktree, which git updates whenever it records a change of its files.
+    pub fn index_file(&self) -> Option<PathBuf> {
+        Some(self.git_files()?.git_dir.join("index"))
+    }
+
+    /// Find the git directory of the worktree above our path, as git would do by default.
+    ///
+    /// This is `None` where git could find it differently, we then run git instead.
+    fn git_files(&self) -> Option<GitFiles> {
+        if ["GIT_DIR", "GIT_COMMON_DIR", "GIT_INDEX_FILE"]
+            .iter()
+            .any(|var| std::env::var_os(var).is_some())
+        {
+            return None;
+        }
+
+        let dot_git = self
+            .path
+            .ancestors()
+            .map(|dir| dir.join(".git"))
+            .find(|dot_git| dot_git.exists())?;
+
+        // A worktree or submodule refers to its git directory with a file.
+        let git_dir = if dot_git.is_file() {
+            let content = fs::read_to_string(&dot_git).ok()?;
+            let git_dir = content.strip_prefix("gitdir: ")?.trim_end();
+            dot_git.parent()?.join(git_dir)
+        } else {
+            dot_git
+        };
+
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common_dir) => git_dir.join(common_dir.trim_end()),
+            Err(_) => git_dir.clone(),
+        };
+
+        Some(GitFiles {
+            git_dir,
+            common_dir,
+        })
+    }
+
+    /// Find the submodules at, above or below any of `paths` in `HEAD`.
+    pub fn submodules(&self, git: &Git, paths: &[&Path]) -> Vec<Submodule> {
+        find_submodules(|| self.exec(git), "HEAD", paths)
+    }
+
+    /// Find the LFS pointers among the files under `paths`, but not `excludes`, in `head`.
+    pub fn lfs_pointers(
+        &self,
+        git: &Git,
+        head: &CommitId,
+        paths: &[&Path],
+        excludes: &[&Path],
+    ) -> Vec<(String, lfs::Pointer)> {
+        find_lfs_pointers(|| self.exec(git), &head.0, paths, excludes)
+    }
+
+    /// The local store of LFS objects.
+    pub fn lfs_objects(&self, git: &Git) -> PathBuf {
+        git.common_dir(&self.path).join("lfs").join("objects")
+    }
+
+    /// Open the checkout of a submodule of this repository.
+    pub fn submodule(&self, git: &Git, submodule: &Submodule) -> CrateDir {
+        let mut cmd = self.exec(git);
+        cmd.args(["rev-parse", "--show-toplevel"]);
+        cmd.stdout(Stdio::piped());
+
+        let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+        if !exit.status.success() {
+            inconclusive(&mut "Git operation was not successful");
+        }
+
+        let toplevel =
+            String::from_utf8(exit.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+        let path = Path::new(toplevel.trim_end()).join(&submodule.path);
+
+        // An uninitialized submodule is an empty directory within the superproject.
+        let dir = CrateDir { path };
+        let mut cmd = dir.exec(git);
+        cmd.args(["rev-parse", "--show-prefix"]);
+        cmd.stdout(Stdio::piped());
+
+        let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+        if !exit.status.success() || !String::from_utf8_lossy(&exit.stdout).trim().is_empty() {
+            inconclusive(&mut format!(
+                "The submodule {} is not checked out, run `git submodule update --init`",
+                submodule.path.display()
+            ));
+        }
+
+        dir
+    }
+
+    /// Check that all paths are tracked, and report those that differ from `HEAD`.
+    ///
+    /// Ignored and untracked files are an error. Modified files are returned, it is up to the
+    /// caller to decide if these are acceptable.
+    pub fn tracked(
+        &self,
+        git: &Git,
+        paths: &mut dyn Iterator<Item = PathSpec<'_>>,
+    ) -> Vec<DirtyFile> {
+        let mut cmd = self.exec(git);
+        cmd.stdout(Stdio::piped());
+        // Do not refresh the index. Its stat data is part of the fingerprint of a clean check, which
+        // must see the same index before and after the check.
+        cmd.arg("--no-optional-locks");
         cmd.args([
             "status",
             "--no-renames",
             "--ignored=matching",
             "--porcelain=v2",
-            "--short",
             "-z",
         ]);
         cmd.arg("--");
-        let mut any = false;
-        cmd.args(paths.map(|st| {
-            any = true;
-            st.to_string()
-        }));
+        let specs: Vec<String> = paths.map(|st| st.to_string()).collect();
+        cmd.args(&specs);
 
-        if !any {
-            return;
+        if specs.is_empty() {
+            return vec![];
         }
 
-        let output = cmd
-            .output()
-            .unwrap_or_else(|mut err| inconclusive(&mut err));
+        let output = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
         let items =
             String::from_utf8(output.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+
+        let mut dirty = vec![];
         for item in items.split('\0') {
             if item.starts_with('!') {
                 eprintln!("{}", item);
@@ -138,35 +424,132 @@ impl CrateDir {
             } else if item.starts_with('?') {
                 eprintln!("{}", item);
                 inconclusive(&mut "Your test depends on untracked file(s)");
+            } else if let Some(file) = DirtyFile::from_porcelain_v2(item) {
+                dirty.push(file);
             }
         }
+
+        self.check_links(git, &specs);
+        dirty
     }
 
+    /// Check that all symbolic links in the index under `specs` point to a file or directory
+    /// under `specs`, so that the data never contains dangling links.
+    fn check_links(&self, git: &Git, specs: &[String]) {
+        let mut cmd = self.exec(git);
+        cmd.args(["ls-files", "-s", "-z", "--full-name", "--"]);
+        cmd.args(specs);
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+        if !exit.status.success() {
+            eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
+            inconclusive(&mut "Git operation was not successful");
+        }
+
+        let listing =
+            String::from_utf8(exit.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+        let mut files = vec![];
+        let mut links = vec![];
+        for entry in listing.split_terminator('\0') {
+            // Format: `<mode> SP <object> SP <stage> TAB <file>`
+            let (info, name) = match entry.split_once('\t') {
+                Some(split) => split,
+                None => continue,
+            };
+
+            if let ["120000", oid, _] = info.split(' ').collect::<Vec<_>>()[..] {
+                links.push((name, oid));
+            }
+            files.push(Path::new(name));
+        }
+
+        if links.is_empty() {
+            return;
+        }
+
+        let oids: Vec<&str> = links.iter().map(|&(_, oid)| oid).collect();
+        let targets = read_blobs(|| self.exec(git), &oids);
+
+        let mut dangling = false;
+        for ((name, _), target) in links.iter().zip(&targets) {
+            let inside = resolve_link(name, target).filter(|resolved| {
+                // Any file at or below the target, all of which are registered.
+                files.iter().any(|file| file.starts_with(resolved))
+            });
+
+            if inside.is_none() {
+                eprintln!("{} -> {}", name, String::from_utf8_lossy(target));
+                dangling = true;
+            }
+        }
+
+        if dangling {
+            inconclusive(
+                &mut "Your test data contains symbolic link(s) to paths that were not registered",
+            );
+        }
+    }
+
+    /// List all files in the index under a path, relative to the repository root, in tree order.
+    ///
+    /// The index is sorted in the same order as a recursive listing of its tree would be.
+    pub fn list_files(&self, git: &Git, path: PathSpec<'_>) -> Vec<String> {
+        let mut cmd = self.exec(git);
+        cmd.args(["ls-files", "-z", "--full-name", "--"]);
+        cmd.arg(path.to_string());
+        split_names(cmd)
+    }
+
+    /// A marker in `dir` that `paths` were found tracked and unmodified at `head`.
+    pub fn clean_marker(
+        &self,
+        dir: &Path,
+        head: &CommitId,
+        paths: &mut dyn Iterator<Item = PathSpec<'_>>,
+    ) -> PathBuf {
+        let filter: PathSpecFilter = paths.collect();
+        dir.join(filter.stamp_name(head) + ".clean")
+    }
+
+    /// Write a pack with the objects of `head` that are needed to checkout `paths`.
+    ///
+    /// A stamp named by the commit and the sparse filter is left in the directory, and a pack that
+    /// was already written for the same commit and filter is not generated again.
     pub fn pack_objects(
         &self,
         git: &Git,
+        head: &CommitId,
         paths: &mut dyn Iterator<Item = PathSpec<'_>>,
         pack_name: OsString,
     ) {
         let _lock = FileWaitLock::for_git_dir(&self.path);
+        let filter: PathSpecFilter = paths.collect();
 
-        let PathSpecFilter {
-            simple_filter,
-            complex_paths,
-        } = paths.collect();
-        let sparse = self.sparse_rev_list(git, &simple_filter);
-
-        if !complex_paths.is_empty() {
+        if !filter.complex_paths.is_empty() {
             inconclusive(&mut "Sorry, paths too complex to pack reliably");
         }
 
+        let stamp = Path::new(&pack_name).join(filter.stamp_name(head) + ".stamp");
+        if stamp.exists() {
+            return;
+        }
+
+        let CommitId(filter) = self
+            .hash_sparse_oid(git, &filter.sparse_patterns())
+            .unwrap_or_else(|mut err| inconclusive(&mut err));
+
+        let sparse = self.sparse_rev_list(git, head, &filter);
+
         let mut cmd = self.exec(git);
         cmd.args(["pack-objects"]);
         cmd.arg(Path::new(&pack_name).join("xtest-data"));
         cmd.stdin(Stdio::piped());
+        cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
 
-        let mut running = cmd.spawn().unwrap_or_else(|mut err| inconclusive(&mut err));
+        let mut running = log::spawn(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
         let stdin = running.stdin.as_mut().expect("Spawned with stdio-piped");
         std::io::Write::write_all(stdin, &sparse).unwrap_or_else(|mut err| inconclusive(&mut err));
         running.stdin = None;
@@ -179,25 +562,22 @@ impl CrateDir {
             eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
             inconclusive(&mut "Git operation was not successful");
         }
-    }
 
-    fn sparse_rev_list(&self, git: &Git, paths: &[PathSpec<'_>]) -> Vec<u8> {
-        let CommitId(oid) = self
-            .hash_sparse_oid(git, paths)
-            .unwrap_or_else(|mut err| inconclusive(&mut err));
+        // Records the name of the pack, which is the hash of its content.
+        std::fs::write(&stamp, &exit.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+    }
 
+    fn sparse_rev_list(&self, git: &Git, head: &CommitId, oid: &str) -> Vec<u8> {
         let list_for = |filterspec| {
             let mut cmd = self.exec(git);
             // Shallow, and sparse filtered, list of objects.
             cmd.args(["rev-list", "-n", "1", "--objects", "--no-object-names"]);
             cmd.arg(filterspec);
-            cmd.arg("HEAD");
+            cmd.arg(head);
             cmd.stdout(Stdio::piped());
             cmd.stderr(Stdio::piped());
 
-            let exit = cmd
-                .output()
-                .unwrap_or_else(|mut err| inconclusive(&mut err));
+            let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
             if !exit.status.success() {
                 eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
                 inconclusive(&mut "Git operation was not successful");
@@ -213,21 +593,17 @@ impl CrateDir {
         objects
     }
 
-    fn hash_sparse_oid(&self, git: &Git, paths: &[PathSpec<'_>]) -> std::io::Result<CommitId> {
+    fn hash_sparse_oid(&self, git: &Git, patterns: &str) -> std::io::Result<CommitId> {
         let mut cmd = self.exec(git);
         cmd.args(["hash-object", "-w", "--stdin"]);
         cmd.stdin(Stdio::piped());
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
 
-        let mut running = cmd.spawn().unwrap_or_else(|mut err| inconclusive(&mut err));
+        let mut running = log::spawn(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
         let stdin = running.stdin.as_mut().expect("Spawned with stdio-piped");
-        for path in paths {
-            use std::io::Write;
-            let path = path.as_encompassing_path().expect("Only simple paths");
-            write!(stdin, "{}\0", path.display()).unwrap_or_else(|mut err| inconclusive(&mut err));
-        }
-
+        std::io::Write::write_all(stdin, patterns.as_bytes())
+            .unwrap_or_else(|mut err| inconclusive(&mut err));
         running.stdin = None;
         let exit = running
             .wait_with_output()
@@ -248,23 +624,53 @@ impl CrateDir {
 #[derive(Default)]
 struct PathSpecFilter<'lt> {
     simple_filter: Vec<PathSpec<'lt>>,
+    exclude_filter: Vec<PathSpec<'lt>>,
     complex_paths: Vec<PathSpec<'lt>>,
 }
 
+impl PathSpecFilter<'_> {
+    /// The simple paths and exclusions as a filter in sparse-checkout format.
+    fn sparse_patterns(&self) -> String {
+        let mut patterns = String::new();
+        // One anchored pattern per line.
+        for path in &self.simple_filter {
+            let path = path.as_encompassing_path().expect("Only simple paths");
+            patterns.push_str(&format!("/{}\n", path.display()));
+        }
+        // The last matching pattern wins, so these must come after all paths.
+        for exclude in &self.exclude_filter {
+            let path = exclude.as_excluded_path().expect("Only exclusions");
+            patterns.push_str(&format!("!/{}\n", path.display()));
+        }
+        patterns
+    }
+
+    /// A name for `head` with this filter, computed without asking git for anything.
+    fn stamp_name(&self, head: &CommitId) -> String {
+        use sha2::Digest;
+        let digest = sha2::Sha256::digest(self.sparse_patterns());
+        format!("{}-{:x}", head, digest)
+    }
+}
+
 impl<'lt> Extend<PathSpec<'lt>> for PathSpecFilter<'lt> {
     fn extend<T: IntoIterator<Item = PathSpec<'lt>>>(&mut self, paths: T) {
         let simple_filter = &mut self.simple_filter;
-        let complex = paths.into_iter().filter_map(|path| {
-            if let Some(sparse_compatible) = path.as_encompassing_path() {
-                // Look, we don't have proper escaping for it yet and no NUL separator.
-                let format = sparse_compatible.display().to_string();
-                // Assuming that this is fine.
-                if !format.contains('\n') && !format.contains('\0') {
-                    simple_filter.push(path);
-                    return None;
-                }
+        let exclude_filter = &mut self.exclude_filter;
+        let sparse_compatible = |path: &Path| {
+            // Look, we don't have proper escaping for it yet and no NUL separator.
+            let format = path.display().to_string();
+            // Assuming that this is fine.
+            !format.contains('\n') && !format.contains('\0')
+        };
 
-                Some(path)
+        let complex = paths.into_iter().filter_map(|path| {
+            if path.as_encompassing_path().is_some_and(sparse_compatible) {
+                simple_filter.push(path);
+                None
+            } else if path.as_excluded_path().is_some_and(sparse_compatible) {
+                exclude_filter.push(path);
+                None
             } else {
                 Some(path)
             }
@@ -286,6 +692,12 @@ impl ShallowBareRepository {
         let mut cmd = Command::new(&git.bin);
         cmd.arg("--git-dir");
         cmd.arg(&self.path);
+        // A globally installed LFS filter would try to fetch objects. We substitute them instead.
+        cmd.env("GIT_LFS_SKIP_SMUDGE", "1");
+        // Reproduce links and executable bits, whatever the user configured.
+        if cfg!(unix) {
+            cmd.args(["-c", "core.symlinks=true", "-c", "core.fileMode=true"]);
+        }
         // Ensure we open _no_ handles.
         // Override this later if necessary.
         cmd.stdout(Stdio::null());
@@ -293,17 +705,282 @@ impl ShallowBareRepository {
         cmd
     }
 
-    pub fn unpack(&self, git: &Git, packs: &OsString) {
+    /// List all files at `head` under a path, relative to the repository root, in tree order.
+    pub fn list_files(&self, git: &Git, head: &CommitId, path: PathSpec<'_>) -> Vec<String> {
+        let mut cmd = self.exec(git);
+        cmd.args(["ls-tree", "-r", "-z", "--name-only", "--full-tree"]);
+        cmd.arg(head);
+        cmd.arg("--");
+        cmd.arg(path.to_string());
+        split_names(cmd)
+    }
+
+    /// Check that the files under a path in `dir` are exactly the blobs of a commit.
+    ///
+    /// Files under `excludes` are not compared. Returns `false` if any file is missing, differs, or
+    /// is not a regular blob.
+    pub fn verify_files(
+        &self,
+        git: &Git,
+        head: &CommitId,
+        dir: &Path,
+        path: &PathSpec<'_>,
+        excludes: &[&Path],
+    ) -> bool {
+        let mut cmd = self.exec(git);
+        cmd.args(["ls-tree", "-r", "-z", "--full-tree"]);
+        cmd.arg(head);
+        cmd.arg("--");
+        cmd.arg(path.to_string());
+        cmd.stdout(Stdio::piped());
+
+        let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+        if !exit.status.success() {
+            eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
+            inconclusive(&mut "Git operation was not successful");
+        }
+
+        let listing =
+            String::from_utf8(exit.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+        let mut expected = vec![];
+        let mut stdin = String::new();
+        for entry in listing.split_terminator('\0') {
+            let (mode, oid, name) = match split_tree_entry(entry) {
+                Some(entry) => entry,
+                None => return false,
+            };
+
+            if is_excluded(name, excludes) {
+                continue;
+            }
+
+            // Symbolic links, gitlinks: cargo does not preserve them.
+            if !(mode == "100644" || mode == "100755") || name.contains('\n') {
+                return false;
+            }
+
+            let file = dir.join(name);
+            match fs::symlink_metadata(&file) {
+                Ok(meta) if meta.is_file() && has_mode(&meta, mode) => {}
+                _ => return false,
+            }
+
+            expected.push(oid.to_owned());
+            stdin.push_str(&format!("{}\n", file.display()));
+        }
+
+        if expected.is_empty() {
+            return false;
+        }
+
+        let mut cmd = self.exec(git);
+        cmd.args(["hash-object", "--stdin-paths"]);
+        cmd.stdin(Stdio::piped());
+        cmd.stdout(Stdio::piped());
+
+        let mut running = log::spawn(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+        let mut input = running.stdin.take().expect("Spawned with stdio-piped");
+        let writer =
+            std::thread::spawn(move || std::io::Write::write_all(&mut input, stdin.as_bytes()));
+        let exit = running
+            .wait_with_output()
+            .unwrap_or_else(|mut err| inconclusive(&mut err));
+        let _ = writer.join();
+
+        if !exit.status.success() {
+            return false;
+        }
+
+        let actual = String::from_utf8_lossy(&exit.stdout);
+        actual.lines().eq(expected.iter().map(String::as_str))
+    }
+
+    /// Check that a checkout of `paths`, without `excludes`, into `dir` has the file types and
+    /// modes of `head`.
+    ///
+    /// Aborts if any executable bit or symbolic link was not reproduced.
+    pub fn verify_modes(
+        &self,
+        git: &Git,
+        head: &CommitId,
+        dir: &Path,
+        paths: &[&Path],
+        excludes: &[&Path],
+    ) {
+        if paths.is_empty() {
+            return;
+        }
+
+        let mut cmd = self.exec(git);
+        cmd.args(["ls-tree", "-r", "-z", "--full-tree"]);
+        cmd.arg(head);
+        cmd.arg("--");
+        cmd.args(paths.iter().map(|path| PathSpec::Path(path).to_string()));
+        cmd.stdout(Stdio::piped());
+
+        let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+        if !exit.status.success() {
+            eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
+            inconclusive(&mut "Git operation was not successful");
+        }
+
+        let listing =
+            String::from_utf8(exit.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+        let mut differ = vec![];
+        let mut links = vec![];
+        for entry in listing.split_terminator('\0') {
+            let (mode, oid, name) = match split_tree_entry(entry) {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            if is_excluded(name, excludes) {
+                continue;
+            }
+
+            let meta = fs::symlink_metadata(dir.join(name));
+            match mode {
+                "100644" | "100755" => match meta {
+                    Ok(meta) if meta.is_file() && has_mode(&meta, mode) => {}
+                    _ => differ.push(name),
+                },
+                // Without symbolic links, git writes the target into a regular file instead.
+                "120000" if cfg!(unix) => match meta {
+                    Ok(meta) if meta.file_type().is_symlink() => links.push((name, oid)),
+                    _ => differ.push(name),
+                },
+                _ => {}
+            }
+        }
+
+        let oids: Vec<&str> = links.iter().map(|&(_, oid)| oid).collect();
+        let targets = read_blobs(|| self.exec(git), &oids);
+        for ((name, _), target) in links.into_iter().zip(targets) {
+            match fs::read_link(dir.join(name)) {
+                Ok(link) if link.as_os_str().to_str().map(str::as_bytes) == Some(&target[..]) => {}
+                _ => differ.push(name),
+            }
+        }
+
+        if !differ.is_empty() {
+            for name in &differ {
+                eprintln!("{}", name);
+            }
+            inconclusive(&mut "The checkout did not reproduce the mode of data file(s)");
+        }
+    }
+
+    /// The id of the blob or tree at `path` in `head`, if it exists.
+    pub fn object_id(&self, git: &Git, head: &CommitId, path: &Path) -> Option<String> {
+        object_id(|| self.exec(git), &head.0, path)
+    }
+
+    /// Find the submodules at, above or below any of `paths` in `head`.
+    pub fn submodules(&self, git: &Git, head: &CommitId, paths: &[&Path]) -> Vec<Submodule> {
+        find_submodules(|| self.exec(git), &head.0, paths)
+    }
+
+    /// Find the LFS pointers among the files under `paths`, but not `excludes`, in `head`.
+    pub fn lfs_pointers(
+        &self,
+        git: &Git,
+        head: &CommitId,
+        paths: &[&Path],
+        excludes: &[&Path],
+    ) -> Vec<(String, lfs::Pointer)> {
+        find_lfs_pointers(|| self.exec(git), &head.0, paths, excludes)
+    }
+
+    /// Also treat `commit` as a boundary of the available history.
+    ///
+    /// Required for the commits of submodules, of which we only have a single commit as well.
+    pub fn add_shallow(&self, commit: &CommitId) {
+        let _lock = FileWaitLock::for_git_dir(&self.path);
+        let shallow = self.path.join("shallow");
+        let mut content =
+            std::fs::read_to_string(&shallow).unwrap_or_else(|mut err| inconclusive(&mut err));
+
+        if !content.lines().any(|line| line == commit.0) {
+            content.push_str(&commit.0);
+            content.push('\n');
+            std::fs::write(&shallow, content).unwrap_or_else(|mut err| inconclusive(&mut err));
+        }
+    }
+
+    /// Reference the objects of an existing repository instead of unpacking our own.
+    ///
+    /// The repository may be bare or have a worktree. Nothing is copied, git reads the objects
+    /// through `objects/info/alternates`.
+    pub fn add_alternate(&self, git: &Git, repo: &Path) {
+        let objects = git.common_dir(repo).join("objects");
+
+        let _lock = FileWaitLock::for_git_dir(&self.path);
+        let info = self.path.join("objects").join("info");
+        std::fs::create_dir_all(&info).unwrap_or_else(|mut err| inconclusive(&mut err));
+
+        let alternates = info.join("alternates");
+        let mut content = match std::fs::read_to_string(&alternates) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(mut err) => inconclusive(&mut err),
+        };
+
+        let line = objects.to_str().unwrap_or_else(|| {
+            inconclusive(&mut format!(
+                "Object directory is not valid UTF-8: {}",
+                objects.display()
+            ))
+        });
+
+        if !content.lines().any(|existing| existing == line) {
+            content.push_str(line);
+            content.push('\n');
+            std::fs::write(&alternates, content).unwrap_or_else(|mut err| inconclusive(&mut err));
+        }
+    }
+
+    /// Check that the commit object itself is available.
+    pub fn has_commit(&self, git: &Git, head: &CommitId) -> bool {
+        let mut cmd = self.exec(git);
+        cmd.args(["cat-file", "-e"]);
+        cmd.arg(format!("{}^{{commit}}", head.0));
+        log::status(&mut cmd)
+            .unwrap_or_else(|mut err| inconclusive(&mut err))
+            .success()
+    }
+
+    /// Unpack the objects for `head` from a pack directory.
+    ///
+    /// Packs are stored in a subdirectory named by the commit. Packs directly in the directory,
+    /// as written by earlier versions, are used when there is no such subdirectory.
+    pub fn unpack(&self, git: &Git, packs: &OsString, head: &CommitId) {
         let _lock = FileWaitLock::for_git_dir(&self.path);
 
+        let keyed = Path::new(packs).join(&head.0);
+        let packs = if keyed.is_dir() {
+            keyed.as_path()
+        } else {
+            Path::new(packs)
+        };
+
         let opendir = std::fs::read_dir(packs).unwrap_or_else(|mut err| inconclusive(&mut err));
 
+        // Packs are named by their content. Those we have seen before, in this directory or by an
+        // earlier test sharing the repository, have nothing new for us.
+        let seen_path = self.path.join("xtest-data-unpacked");
+        let mut seen = match std::fs::read_to_string(&seen_path) {
+            Ok(seen) => seen,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(mut err) => inconclusive(&mut err),
+        };
+
         for entry in opendir.filter_map(Result::ok) {
-            if !entry
-                .path()
-                .to_str()
-                .map_or(false, |st| st.ends_with("pack"))
-            {
+            if !entry.path().to_str().is_some_and(|st| st.ends_with("pack")) {
+                continue;
+            }
+
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if seen.lines().any(|line| line == name) {
                 continue;
             }
 
@@ -314,7 +991,7 @@ impl ShallowBareRepository {
             git.args(["unpack-objects", "-r"]);
             git.stdin(Stdio::piped());
 
-            let mut cmd = git.spawn().unwrap_or_else(|mut err| inconclusive(&mut err));
+            let mut cmd = log::spawn(&mut git).unwrap_or_else(|mut err| inconclusive(&mut err));
             let mut stdin = cmd.stdin.as_mut().expect("Supplied with Stdio::piped");
 
             std::io::copy(&mut file, &mut stdin).unwrap_or_else(|mut err| inconclusive(&mut err));
@@ -329,6 +1006,10 @@ impl ShallowBareRepository {
                 eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
                 inconclusive(&mut "Git operation was not successful");
             }
+
+            seen.push_str(&name);
+            seen.push('\n');
+            std::fs::write(&seen_path, &seen).unwrap_or_else(|mut err| inconclusive(&mut err));
         }
     }
 
@@ -346,6 +1027,7 @@ impl ShallowBareRepository {
 
         let PathSpecFilter {
             simple_filter,
+            exclude_filter,
             complex_paths,
         } = paths.collect();
 
@@ -353,9 +1035,7 @@ impl ShallowBareRepository {
         cmd.args(["worktree", "add", "--no-checkout"]);
         cmd.arg(worktree);
         cmd.arg(head);
-        let exit = cmd
-            .output()
-            .unwrap_or_else(|mut err| inconclusive(&mut err));
+        let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
         if !exit.status.success() {
             eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
             inconclusive(&mut "Git operation was not successful");
@@ -369,14 +1049,21 @@ impl ShallowBareRepository {
             cmd.arg(worktree);
             cmd.args(["sparse-checkout", "--no-cone", "set", "--stdin"]);
             cmd.stdin(Stdio::piped());
-            let mut running = cmd.spawn()?;
+            let mut running = log::spawn(&mut cmd)?;
             let stdin = running.stdin.as_mut().expect("Spawned with stdio-piped");
             for path in &simple_filter {
                 let simple = path.as_encompassing_path().unwrap().display().to_string();
                 use std::io::Write;
                 // > This includes interpreting pathnames that begin with a double quote (") as C-style quoted strings.
                 // Since there is no NUL separation (yet?) we use this.
-                writeln!(stdin, "{}", simple).unwrap_or_else(|mut err| inconclusive(&mut err));
+                // If git fails early, the pipe is closed. That is handled as a fallback instead.
+                writeln!(stdin, "{}", simple)?;
+            }
+            // The last matching pattern wins, so these must come after all paths.
+            for exclude in &exclude_filter {
+                let excluded = exclude.as_excluded_path().unwrap().display().to_string();
+                use std::io::Write;
+                writeln!(stdin, "!{}", excluded)?;
             }
             running.stdin = None;
             let exit = running.wait_with_output()?;
@@ -391,7 +1078,10 @@ impl ShallowBareRepository {
                 "Version of Git appears to not support sparse-checkout: {}",
                 err
             );
-            let mut all_again = simple_filter.into_iter().chain(complex_paths);
+            let mut all_again = simple_filter
+                .into_iter()
+                .chain(complex_paths)
+                .chain(exclude_filter);
             return self.checkout_fallback_slow(git, worktree, head, &mut all_again);
         }
 
@@ -402,16 +1092,15 @@ impl ShallowBareRepository {
         cmd.arg("--force");
         cmd.arg(&head.0);
         cmd.stderr(Stdio::piped());
-        let exit = cmd
-            .output()
-            .unwrap_or_else(|mut err| inconclusive(&mut err));
+        let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
 
         if !exit.status.success() {
             eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
             inconclusive(&mut "Git operation was not successful");
         }
 
-        self.checkout_fallback_slow(git, worktree, head, &mut complex_paths.into_iter());
+        let mut complex = complex_paths.into_iter().chain(exclude_filter);
+        self.checkout_fallback_slow(git, worktree, head, &mut complex);
     }
 
     /// A version of `checkout` that uses checkout and a list pathspecs from stdin to determine the
@@ -424,6 +1113,16 @@ impl ShallowBareRepository {
         head: &CommitId,
         paths: &mut dyn Iterator<Item = PathSpec<'_>>,
     ) {
+        let paths: Vec<PathSpec<'_>> = paths.collect();
+        // Without any pathspec, this would checkout the complete tree instead. The same holds if
+        // there are only exclusions.
+        if paths
+            .iter()
+            .all(|path| matches!(path, PathSpec::Exclude(_)))
+        {
+            return;
+        }
+
         let mut cmd = self.exec(git);
         cmd.arg("--work-tree");
         cmd.arg(worktree);
@@ -431,7 +1130,7 @@ impl ShallowBareRepository {
         cmd.args(["--pathspec-from-file=-", "--pathspec-file-nul"]);
         cmd.arg(&head.0);
         cmd.stdin(Stdio::piped());
-        let mut running = cmd.spawn().unwrap_or_else(|mut err| inconclusive(&mut err));
+        let mut running = log::spawn(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
         let stdin = running.stdin.as_mut().expect("Spawned with stdio-piped");
         for path in paths {
             use std::io::Write;
@@ -448,6 +1147,299 @@ impl ShallowBareRepository {
     }
 }
 
+impl GitFiles {
+    /// Resolve `HEAD` from a loose or packed reference, or `None` if it is stored any other way.
+    fn head(&self) -> Option<CommitId> {
+        let is_id =
+            |id: &str| matches!(id.len(), 40 | 64) && id.bytes().all(|ch| ch.is_ascii_hexdigit());
+
+        let head = fs::read_to_string(self.git_dir.join("HEAD")).ok()?;
+        let reference = match head.trim_end().strip_prefix("ref: ") {
+            Some(reference) => reference,
+            None => return is_id(head.trim_end()).then(|| CommitId::from(head.as_str())),
+        };
+
+        // Some references are specific to a worktree, most are shared.
+        for dir in [&self.git_dir, &self.common_dir] {
+            if let Ok(id) = fs::read_to_string(dir.join(reference)) {
+                return is_id(id.trim_end()).then(|| CommitId::from(id.as_str()));
+            }
+        }
+
+        let packed = fs::read_to_string(self.common_dir.join("packed-refs")).ok()?;
+        packed
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .find(|&(id, name)| name == reference && is_id(id))
+            .map(|(id, _)| CommitId::from(id))
+    }
+}
+
+/// Split a line of `ls-tree` output into mode, object id and name.
+fn split_tree_entry(entry: &str) -> Option<(&str, &str, &str)> {
+    // Format: `<mode> SP <type> SP <object> TAB <file>`
+    let (info, name) = entry.split_once('\t')?;
+    let mut info = info.split(' ');
+    match (info.next(), info.nth(1)) {
+        (Some(mode), Some(oid)) => Some((mode, oid, name)),
+        _ => None,
+    }
+}
+
+/// Check if a file, named relative to the repository root, is under any of the excluded paths.
+///
+/// This is for listings of `ls-tree`, which does not support exclusions in its pathspecs.
+pub(crate) fn is_excluded(name: &str, excludes: &[&Path]) -> bool {
+    excludes
+        .iter()
+        .any(|exclude| Path::new(name).starts_with(exclude))
+}
+
+/// Resolve a path, relative to the repository root, in the tree of `rev`.
+fn object_id(mut exec: impl FnMut() -> Command, rev: &str, path: &Path) -> Option<String> {
+    let mut cmd = exec();
+    cmd.args(["rev-parse", "--verify", "--quiet"]);
+    cmd.arg(format!("{}:{}", rev, path.display()));
+    cmd.stdout(Stdio::piped());
+
+    let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+    if !exit.status.success() {
+        return None;
+    }
+
+    let id = String::from_utf8(exit.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+    Some(id.trim_end().to_owned())
+}
+
+/// Check the executable bit of a file against its mode in a tree.
+#[cfg(unix)]
+fn has_mode(meta: &fs::Metadata, mode: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    let executable = meta.permissions().mode() & 0o111 != 0;
+    executable == (mode == "100755")
+}
+
+#[cfg(not(unix))]
+fn has_mode(_: &fs::Metadata, _: &str) -> bool {
+    // There is no executable bit to compare.
+    true
+}
+
+/// The path, relative to the repository root, that a symbolic link at `name` points to.
+///
+/// Returns `None` for absolute targets and those leaving the repository.
+fn resolve_link(name: &str, target: &[u8]) -> Option<PathBuf> {
+    let target = Path::new(std::str::from_utf8(target).ok()?);
+    let mut resolved = Path::new(name).parent()?.to_path_buf();
+    for component in target.components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(resolved)
+}
+
+/// Find the blobs under `paths` in the tree of `rev` that are LFS pointers.
+fn find_lfs_pointers(
+    mut exec: impl FnMut() -> Command,
+    rev: &str,
+    paths: &[&Path],
+    excludes: &[&Path],
+) -> Vec<(String, lfs::Pointer)> {
+    if paths.is_empty() {
+        return vec![];
+    }
+
+    let mut cmd = exec();
+    cmd.args(["ls-tree", "-r", "-l", "-z", "--full-tree"]);
+    cmd.arg(rev);
+    cmd.arg("--");
+    cmd.args(paths.iter().map(|path| PathSpec::Path(path).to_string()));
+    cmd.stdout(Stdio::piped());
+
+    let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+    if !exit.status.success() {
+        eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
+        inconclusive(&mut "Git operation was not successful");
+    }
+
+    let listing = String::from_utf8(exit.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+    let mut candidates = vec![];
+    for entry in listing.split_terminator('\0') {
+        // Format: `<mode> SP <type> SP <object> SP+ <size> TAB <file>`
+        let (info, name) = match entry.split_once('\t') {
+            Some(split) => split,
+            None => continue,
+        };
+
+        if is_excluded(name, excludes) {
+            continue;
+        }
+
+        let info: Vec<_> = info.split_whitespace().collect();
+        if let [_, "blob", oid, size] = info[..] {
+            if size
+                .parse()
+                .is_ok_and(|size: u64| size <= lfs::MAX_POINTER_SIZE)
+            {
+                candidates.push((oid.to_owned(), name.to_owned()));
+            }
+        }
+    }
+
+    if candidates.is_empty() {
+        return vec![];
+    }
+
+    let oids: Vec<&str> = candidates.iter().map(|(oid, _)| oid.as_str()).collect();
+    let blobs = read_blobs(exec, &oids);
+
+    let mut pointers = vec![];
+    for ((_, name), content) in candidates.into_iter().zip(blobs) {
+        if let Some(pointer) = lfs::Pointer::parse(&content) {
+            pointers.push((name, pointer));
+        }
+    }
+
+    pointers
+}
+
+/// Read the contents of blobs, in the order of `oids`.
+fn read_blobs(mut exec: impl FnMut() -> Command, oids: &[&str]) -> Vec<Vec<u8>> {
+    if oids.is_empty() {
+        return vec![];
+    }
+
+    let mut cmd = exec();
+    cmd.args(["cat-file", "--batch"]);
+    cmd.stdin(Stdio::piped());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    let mut running = log::spawn(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+    let mut input = running.stdin.take().expect("Spawned with stdio-piped");
+    let oids: String = oids.iter().map(|oid| format!("{}\n", oid)).collect();
+    let writer = std::thread::spawn(move || std::io::Write::write_all(&mut input, oids.as_bytes()));
+    let exit = running
+        .wait_with_output()
+        .unwrap_or_else(|mut err| inconclusive(&mut err));
+    let _ = writer.join();
+
+    if !exit.status.success() {
+        eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
+        inconclusive(&mut "Git operation was not successful");
+    }
+
+    // Format: `<object> SP <type> SP <size> LF <contents> LF`, in order of the input.
+    let mut blobs = vec![];
+    let mut output = &exit.stdout[..];
+    while !output.is_empty() {
+        let header_end = match output.iter().position(|&b| b == b'\n') {
+            Some(end) => end,
+            None => inconclusive(&mut "Unexpected output of `git cat-file`"),
+        };
+
+        let header = String::from_utf8_lossy(&output[..header_end]);
+        let size: usize = header
+            .rsplit(' ')
+            .next()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or_else(|| inconclusive(&mut "Unexpected output of `git cat-file`"));
+
+        blobs.push(output[header_end + 1..][..size].to_vec());
+        output = &output[header_end + 1 + size + 1..];
+    }
+
+    blobs
+}
+
+/// Find the submodules in the tree of `rev` that contain, or are contained in, one of `paths`.
+fn find_submodules(
+    mut exec: impl FnMut() -> Command,
+    rev: &str,
+    paths: &[&Path],
+) -> Vec<Submodule> {
+    // Gitlinks at or above a path. `ls-tree` does not look into them for the path itself.
+    let mut ancestors = vec![];
+    for path in paths {
+        for ancestor in path.ancestors() {
+            if !ancestor.as_os_str().is_empty() {
+                ancestors.push(PathSpec::Path(ancestor).to_string());
+            }
+        }
+    }
+
+    // Gitlinks below a path.
+    let below: Vec<_> = paths
+        .iter()
+        .map(|path| PathSpec::Path(path).to_string())
+        .collect();
+
+    let mut submodules: Vec<Submodule> = vec![];
+    for (recursive, specs) in [(false, ancestors), (true, below)] {
+        if specs.is_empty() {
+            continue;
+        }
+
+        let mut cmd = exec();
+        cmd.args(["ls-tree", "-z", "--full-tree"]);
+        if recursive {
+            cmd.arg("-r");
+        }
+        cmd.arg(rev);
+        cmd.arg("--");
+        cmd.args(specs);
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+        if !exit.status.success() {
+            eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
+            inconclusive(&mut "Git operation was not successful");
+        }
+
+        let listing =
+            String::from_utf8(exit.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+        for entry in listing.split_terminator('\0') {
+            match split_tree_entry(entry) {
+                Some(("160000", commit, name))
+                    if !submodules.iter().any(|sub| sub.path == Path::new(name)) =>
+                {
+                    submodules.push(Submodule {
+                        path: PathBuf::from(name),
+                        commit: CommitId::from(commit),
+                    })
+                }
+                _ => {}
+            }
+        }
+    }
+
+    submodules
+}
+
+/// Run a listing command, splitting its NUL-terminated output into names.
+fn split_names(mut cmd: Command) -> Vec<String> {
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    let exit = log::output(&mut cmd).unwrap_or_else(|mut err| inconclusive(&mut err));
+    if !exit.status.success() {
+        eprintln!("{}", String::from_utf8_lossy(&exit.stderr));
+        inconclusive(&mut "Git operation was not successful");
+    }
+
+    let names = String::from_utf8(exit.stdout).unwrap_or_else(|mut err| inconclusive(&mut err));
+    names.split_terminator('\0').map(str::to_owned).collect()
+}
+
 fn inconclusive_but_maybe_gitdir(
     output: &std::process::Output,
     descriptor: &mut dyn std::fmt::Display,
@@ -506,7 +1498,7 @@ impl FileWaitLock {
 
 impl Drop for FileWaitLock {
     fn drop(&mut self) {
-        if let Err(_) = fs2::FileExt::unlock(&self.lock) {
+        if fs2::FileExt::unlock(&self.lock).is_err() {
             // Otherwise we'd block indefinitely in this process?
             std::process::abort();
         }
@@ -519,6 +1511,15 @@ impl PathSpec<'_> {
         match self {
             PathSpec::Path(path) => Some(path),
             // Should return None for a glob-filtered path since that is not supported.
+            PathSpec::Exclude(_) => None,
+        }
+    }
+
+    /// For the negative patterns of git sparse checkout.
+    pub fn as_excluded_path(&self) -> Option<&Path> {
+        match self {
+            PathSpec::Exclude(path) => Some(path),
+            PathSpec::Path(_) => None,
         }
     }
 }
@@ -529,10 +1530,17 @@ impl std::convert::AsRef<OsStr> for CommitId {
     }
 }
 
+impl core::fmt::Display for CommitId {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 impl core::fmt::Display for PathSpec<'_> {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
             PathSpec::Path(path) => write!(f, ":(top,literal){}", path.display()),
+            PathSpec::Exclude(path) => write!(f, ":(top,literal,exclude){}", path.display()),
         }
     }
 }
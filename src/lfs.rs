@@ -0,0 +1,107 @@
+//! Git LFS pointers and the objects they refer to.
+//!
+//! We never run `git lfs`. The objects are read from a local LFS store, e.g. `.git/lfs/objects`,
+//! when packing and from the `lfs/objects` directory next to the pack files in a crate. See the
+//! specification at <https://github.com/git-lfs/git-lfs/blob/main/docs/spec.md>.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Pointer files are small, larger blobs are never pointers.
+pub(crate) const MAX_POINTER_SIZE: u64 = 1024;
+
+/// The content of a pointer file, which replaces the real file in the repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Pointer {
+    /// The hex encoded SHA-256 of the content.
+    pub oid: String,
+    pub size: u64,
+}
+
+impl Pointer {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() as u64 > MAX_POINTER_SIZE {
+            return None;
+        }
+
+        let text = std::str::from_utf8(data).ok()?;
+        let mut lines = text.lines();
+        if !lines
+            .next()?
+            .starts_with("version https://git-lfs.github.com/spec/")
+        {
+            return None;
+        }
+
+        let (mut oid, mut size) = (None, None);
+        for line in lines {
+            if let Some(hex) = line.strip_prefix("oid sha256:") {
+                if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return None;
+                }
+                oid = Some(hex.to_ascii_lowercase());
+            } else if let Some(bytes) = line.strip_prefix("size ") {
+                size = Some(bytes.parse().ok()?);
+            }
+        }
+
+        Some(Pointer {
+            oid: oid?,
+            size: size?,
+        })
+    }
+
+    /// The path of the object within an object directory such as `.git/lfs/objects`.
+    pub fn object_path(&self, objects: &Path) -> PathBuf {
+        objects
+            .join(&self.oid[0..2])
+            .join(&self.oid[2..4])
+            .join(&self.oid)
+    }
+
+    /// Find the object in the first directory that has it.
+    pub fn find(&self, stores: &[PathBuf]) -> Option<PathBuf> {
+        stores
+            .iter()
+            .map(|objects| self.object_path(objects))
+            .find(|path| path.is_file())
+    }
+
+    /// Copy the object to `target`, after checking that it has the size and hash of the pointer.
+    pub fn copy_verified(&self, object: &Path, target: &Path) -> io::Result<()> {
+        let data = fs::read(object)?;
+        if data.len() as u64 != self.size || hex(&sha256(&data)) != self.oid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "LFS object {} does not match its pointer {} ({} bytes)",
+                    object.display(),
+                    self.oid,
+                    self.size
+                ),
+            ));
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Replace, never write through, since the target may be a hard link or read-only.
+        let partial = target.with_extension("xtest-data-partial");
+        fs::write(&partial, &data)?;
+        // Keep the mode of the checked out pointer, such as its executable bit.
+        if let Ok(meta) = fs::metadata(target) {
+            fs::set_permissions(&partial, meta.permissions())?;
+        }
+        fs::rename(&partial, target)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    use sha2::Digest;
+    sha2::Sha256::digest(data).into()
+}
@@ -52,8 +52,15 @@
 //! ```
 #![forbid(unsafe_code)]
 mod git;
+mod lfs;
+mod log;
+mod metadata;
+mod tree;
 
-use std::{borrow::Cow, env, ffi::OsString, fs, io, path::Path, path::PathBuf};
+use std::cell::{Cell, RefCell};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use std::{env, ffi::OsString, fs, io, path::Path, path::PathBuf};
 use tinyjson::JsonValue;
 
 #[cfg(doc)]
@@ -71,23 +78,147 @@ pub struct Files {
     key: usize,
 }
 
+/// The git object id of registered data, a key of its content.
+///
+/// Returned by [`FsData::object_id()`]. Its string form is the hex id of the blob of a file, or
+/// the tree of a directory, as in `git rev-parse <commit>:<path>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ObjectId {
+    id: String,
+    dirty: bool,
+}
+
 #[derive(Debug)]
 enum Managed {
     // TODO: have a spec for the glob `<dir>/**.ext`?
     Files(PathBuf),
+    /// A tree, but not the paths below it.
+    Excluding {
+        path: PathBuf,
+        excludes: Vec<PathBuf>,
+    },
 }
 
 type FsItem<'lt> = &'lt mut PathBuf;
 
+/// A failure to set up the data, returned by [`Setup::try_build()`] and
+/// [`SetupBuilder::try_build()`].
+#[derive(Debug)]
+pub struct Error {
+    message: String,
+}
+
+/// The unwind payload of a failure in a fallible build, see `FALLIBLE`.
+struct Inconclusive(String);
+
+thread_local! {
+    /// Is a fallible build running on this thread? Then `inconclusive` unwinds to return an error.
+    static FALLIBLE: Cell<bool> = const { Cell::new(false) };
+}
+
 /// The product of `Setup`, ensuring local file system accessible test resources.
 ///
 /// This object is used to retrieve the local paths of resources that have been registered with the
 /// method [`Setup::add()`].
+///
+/// Keep it alive for as long as the test uses the paths. Data that was checked out for a crate
+/// archive is removed when this is dropped, except when the test is failing with a panic. Then
+/// the checkout is kept and instructions to reproduce the failure are printed. Set
+/// `CARGO_XTEST_DATA_KEEP_TREE=always` to keep all checkouts.
+///
+/// Paths passed to [`Setup::rewrite()`] may be used after this is dropped. A checkout that they
+/// point into is removed only when the thread of the test exits, and kept if a panic occurred on
+/// that thread before, even one that was caught.
 #[derive(Debug)]
 pub struct FsData {
     /// Map all configured items to their paths.
     /// This map will essentially be constant and we do not care about the VCS interpretation.
     map: Vec<PathBuf>,
+    /// The paths, relative to the manifest, as they were registered.
+    /// Indexed in parallel with `map`.
+    registered: Vec<PathBuf>,
+    /// The paths given to `Setup::rewrite`, as registered and as rewritten.
+    rewritten: Vec<(PathBuf, PathBuf)>,
+    /// The same paths, relative to the root of the repository.
+    vcs_paths: Vec<PathBuf>,
+    /// Paths below the registered trees that were left out, relative to the root of the repository.
+    excludes: Vec<PathBuf>,
+    /// The directory for scratch copies, if the environment provided one.
+    tmpdir: Option<PathBuf>,
+    /// The VCS tree from which the data was materialized.
+    tree: Tree,
+    /// The directory into which `build` checked out data, if any.
+    checkout: Option<Checkout>,
+    /// The commit of the data, determined on first use in a local repository.
+    commit: OnceLock<Option<git::CommitId>>,
+    /// The object ids of the registered paths, each determined on first use.
+    /// Indexed in parallel with `map`.
+    object_ids: Vec<OnceLock<Option<ObjectId>>>,
+    /// Should mismatched golden files be overwritten?
+    bless: bool,
+}
+
+/// A tree that was checked out for one `FsData`, which we remove when it is dropped.
+///
+/// If a test fails while the data is alive, that is the data is dropped while unwinding, the tree
+/// is kept instead so that the failure can be investigated. Set `CARGO_XTEST_DATA_KEEP_TREE` to
+/// `always` to keep all trees.
+#[derive(Debug)]
+struct Checkout {
+    dir: PathBuf,
+    commit_id: git::CommitId,
+    /// The manifest of the crate archive that the test ran in.
+    manifest: PathBuf,
+    /// The variables that selected the data, to reproduce the test with the same data.
+    environment: Vec<(&'static str, OsString)>,
+    /// The registered paths that were checked out.
+    paths: Vec<PathBuf>,
+    /// Were all registered paths resolved into this tree, not the crate archive?
+    complete: bool,
+    /// The test, by the name of its thread.
+    test: Option<String>,
+    /// Did we rewrite paths owned by the caller? These may outlive us, see `OUTLIVING`.
+    rewritten: bool,
+}
+
+thread_local! {
+    /// Checkouts that paths rewritten for the caller point into, after their data was dropped.
+    ///
+    /// The test harness runs each test on a thread of its own, so these are removed when the
+    /// thread exits. The main thread does not run this destructor, its checkouts are kept.
+    static OUTLIVING: Outliving = Outliving::default();
+    /// Did a panic occur on this thread? Set by the hook of `record_panics`.
+    static PANICKED: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Default)]
+struct Outliving {
+    checkouts: RefCell<Vec<Checkout>>,
+}
+
+/// Access to the VCS tree of the data, after it has been materialized.
+#[derive(Debug)]
+enum Tree {
+    /// The data is the local working tree, compared to `HEAD`.
+    Local {
+        git: git::Git,
+        dir: git::CrateDir,
+        submodules: Vec<git::Submodule>,
+    },
+    /// The data was checked out from a commit in our shallow clone.
+    Vcs {
+        git: git::Git,
+        repo: git::ShallowBareRepository,
+        commit_id: git::CommitId,
+        submodules: Vec<git::Submodule>,
+    },
+    /// The data is a tree materialized ahead of time.
+    Materialized {
+        commit_id: git::CommitId,
+        files: Vec<String>,
+    },
+    /// The data is in directories without VCS information.
+    Files,
 }
 
 #[derive(Debug)]
@@ -96,21 +227,31 @@ enum Source {
     VcsFromManifest {
         /// TODO: we should support other commit identifiers.
         commit_id: git::CommitId,
+        /// Was the crate archive packaged from this commit?
+        crate_matches: bool,
         /// Evidence how we plan to access the source.
         git: git::Git,
         /// The directory where we may put git-dir and checkout of the resources.
         datadir: PathBuf,
+        /// The VCS info that was used instead of the one of the crate archive.
+        vcs_info: Option<PathBuf>,
     },
     /// The data will be relative to the crate manifest.
     Local(git::Git),
+    /// The data was checked out ahead of time, see `CARGO_XTEST_DATA_TREE`.
+    Materialized(tree::Materialized),
+    /// The data is relative to the crate manifest, but there is no repository to check it against.
+    Untracked,
 }
 
 #[derive(Default, Debug)]
 struct Resources<'paths> {
-    /// All files and tree that are owned by the `Setup`.
+    /// All files and tree that are owned by the `Setup`, relative to the repository root.
     /// Note: we never intend to remove anything from here. If we did we would have to do some kind
     /// of remapping data structure to ensure that `Files` does not access the wrong item.
     relative_files: Vec<Managed>,
+    /// The same files, relative to the manifest as they were registered but normalized.
+    registered: Vec<PathBuf>,
     /// Resources where we do 'simple' path replacement in a filter style.
     ///
     /// Note on ergonomics: We MAY take several different kinds of paths in the future to allow the
@@ -123,6 +264,8 @@ struct Resources<'paths> {
     /// those items. Basically, adding the crate should not be much more complex than making all
     /// paths a variable and then throwing a `xtest_data::setup!()` on top.
     unmanaged: Vec<FsItem<'paths>>,
+    /// The paths of `unmanaged`, relative to the manifest as they were given but normalized.
+    unmanaged_registered: Vec<PathBuf>,
 }
 
 /// A builder to configure desired test data paths.
@@ -148,25 +291,48 @@ struct Resources<'paths> {
 #[derive(Debug)]
 pub struct Setup<'paths> {
     repository: OsString,
-    manifest: &'static str,
+    manifest: PathBuf,
+    /// The path of the manifest directory in the repository, unless there is none.
+    prefix: Option<PathBuf>,
     /// Have we determined to be local or in a crate?.
     source: Source,
     /// The resources that we store.
     resources: Resources<'paths>,
     /// A git pack archive with files.
     pack_objects: Option<OsString>,
+    /// An existing repository whose objects we can borrow.
+    git_dir: Option<PathBuf>,
+    /// A directory for auxiliary files, if the environment provided one.
+    tmpdir: Option<PathBuf>,
+    /// Should the materialized data be marked read-only?
+    read_only: bool,
+    /// How to treat modified data in a local repository.
+    dirty: DirtyPolicy,
+    /// Should mismatched golden files be overwritten?
+    bless: bool,
+}
+
+/// How to treat test data that differs from the committed state, when writing pack objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DirtyPolicy {
+    /// Abort, the packed data would not reproduce the local test run.
+    Error,
+    /// Print a warning and pack the committed state.
+    Warn,
 }
 
 /// The options determined from the compile time environment of the crate that called us.
 ///
 /// This is every environment data we are gather from the `setup` macro, which allows us to get the
 /// environment flags passed to the _calling_ crate instead of our own. Please do not construct
-/// this directly since doing so could affect the integrity of the information.
+/// this directly since doing so could affect the integrity of the information. A harness that
+/// needs to provide these values uses [`Setup::builder()`] instead.
 ///
 /// This is independent from the data gathered from the _runtime_ environment. It is combined with
 /// that information in `Setup::build`.
 #[doc(hidden)]
 pub struct EnvOptions {
+    pub pkg_name: &'static str,
     pub pkg_repository: &'static str,
     pub manifest_dir: &'static str,
     pub target_tmpdir: Option<&'static str>,
@@ -212,6 +378,7 @@ macro_rules! setup {
             // FIXME: technically this isn't critical information.
             // We could rely on the user passing one to us since we will fail when that is not a
             // git repository with the correct commit ID. That's just their fault.
+            pkg_name: env!("CARGO_PKG_NAME"),
             pkg_repository: env!("CARGO_PKG_REPOSITORY"),
             manifest_dir: env!("CARGO_MANIFEST_DIR"),
             target_tmpdir: option_env!("CARGO_TARGET_TMPDIR"),
@@ -219,108 +386,564 @@ macro_rules! setup {
     };
 }
 
+/// Create test data shared by all tests in the same test binary.
+///
+/// This evaluates to a `&'static` [`FsData`]. The listed paths are registered with a single
+/// [`setup!`] and materialized by [`Setup::build()`] at most once per process, the first time any
+/// test evaluates the expression. All later evaluations return the same data. Retrieve paths from
+/// the shared data with [`FsData::resolve()`].
+///
+/// The data is shared per invocation of the macro, not per process. Each invocation owns a
+/// separate static, so you will usually wrap it in a function. Specs are not merged between
+/// invocations: two invocations, even with the same paths, each build their own data, and a path
+/// is only resolved by the data of an invocation that lists it. List the union of the paths that
+/// your tests need in one invocation to prepare them once.
+///
+/// This avoids paying for repeated status checks, locking and checkouts when many tests in one
+/// binary use the same data. The usual panics of `setup!` and `build` apply.
+///
+/// # Example
+///
+/// ```
+/// fn test_data() -> &'static xtest_data::FsData {
+///     xtest_data::shared!("tests/data.zip")
+/// }
+///
+/// let path = test_data().resolve("tests/data.zip").unwrap();
+/// assert!(path.exists(), "{}", path.display());
+/// ```
+#[macro_export]
+macro_rules! shared {
+    ($($path:expr),* $(,)?) => {{
+        static SHARED: ::std::sync::OnceLock<$crate::FsData> = ::std::sync::OnceLock::new();
+        SHARED.get_or_init(|| {
+            #[allow(unused_mut)]
+            let mut setup = $crate::setup!();
+            $( let _ = setup.add($path); )*
+            setup.build()
+        })
+    }};
+}
+
+/// Declare a test function that receives registered test data as arguments.
+///
+/// Requires the `macros` feature. Each `data = "path"` argument registers a path, relative to the
+/// manifest, as with [`Setup::add()`]. The function is then turned into a test that runs
+/// [`setup!`], calls [`Setup::build()`] and passes the resolved paths as `&Path` arguments, in
+/// the order of registration. All attributes on the function, such as `#[should_panic]`, are kept
+/// on the test.
+///
+/// # Example
+///
+/// ```
+/// #[xtest_data::test(data = "tests/data.zip")]
+/// fn data_exists(datazip: &std::path::Path) {
+///     assert!(datazip.exists(), "{}", datazip.display());
+/// }
+/// ```
+#[cfg(feature = "macros")]
+pub use xtest_data_macros::test;
+
+/// A path of test data, relative to the manifest, that is checked when compiling.
+///
+/// Requires the `macros` feature. This evaluates to a `PathBuf` of the string literal, as with
+/// `PathBuf::from`, for use with [`Setup::rewrite()`] or [`Setup::add()`]. In your repository, a
+/// path that does not exist is a compile error, so that a typo is not first noticed by a packager.
+/// In a crate archive the data is not checked, it is only available once it was fetched.
+///
+/// Each path is also left in the compiled test, behind a marker. `cargo xtest-data pack --no-run`
+/// finds them in the test executables and packs their data without running the tests.
+///
+/// # Example
+///
+/// ```
+/// let mut datazip = xtest_data::path!("tests/data.zip");
+/// xtest_data::setup!().rewrite([&mut datazip]).build();
+///
+/// assert!(datazip.exists(), "{}", datazip.display());
+/// ```
+#[cfg(feature = "macros")]
+pub use xtest_data_macros::path;
+
+/// Find the pack directory for this crate.
+///
+/// A crate specific variable such as `CARGO_XTEST_DATA_PACK_OBJECTS_MY_CRATE` for `my-crate` takes
+/// precedence over the common one, so that one environment can test many crates.
+fn pack_objects_var(pkg_name: Option<&str>) -> Option<OsString> {
+    let specific = pkg_name.and_then(|pkg_name| {
+        let suffix: String = pkg_name
+            .chars()
+            .map(|ch| match ch {
+                '-' => '_',
+                ch => ch.to_ascii_uppercase(),
+            })
+            .collect();
+        env::var_os(format!("CARGO_XTEST_DATA_PACK_OBJECTS_{}", suffix))
+    });
+
+    specific.or_else(|| env::var_os("CARGO_XTEST_DATA_PACK_OBJECTS"))
+}
+
+/// Describe how to fetch the published pack objects, if the manifest of a crate configures them.
+///
+/// Empty if there is no `pack-artifact` in `[package.metadata.xtest-data]`.
+fn fetch_instructions(manifest: &Path) -> String {
+    let meta = match metadata::Metadata::read(manifest) {
+        Some(meta) => meta,
+        None => return String::new(),
+    };
+
+    let url = match meta.pack_artifact() {
+        Some(url) => url,
+        None => return String::new(),
+    };
+
+    let configured = meta.pack_objects();
+    let dir = manifest.join(configured.as_deref().unwrap_or("target/xtest-data"));
+    let mut instructions = format!(
+        "\nThe pack objects of this crate are published at {}. Download and unpack them with:\n  cargo xtest-data fetch {} {}",
+        url,
+        manifest.display(),
+        dir.display(),
+    );
+
+    // Otherwise, the tests find them in the configured directory.
+    if configured.is_none() {
+        instructions.push_str(&format!(
+            "\nThen run the tests with CARGO_XTEST_DATA_PACK_OBJECTS={}",
+            dir.display()
+        ));
+    }
+
+    instructions
+}
+
+/// The variable with paths exported by [`FsData::to_env()`].
+const PATHS_VAR: &str = "CARGO_XTEST_DATA_PATHS";
+
+trait GetKey {
+    fn get_key(&self, key: &str) -> Option<&Self>;
+}
+
+impl GetKey for JsonValue {
+    fn get_key(&self, key: &str) -> Option<&Self> {
+        self.get::<std::collections::HashMap<_, _>>()?.get(key)
+    }
+}
+
+/// Read the commit ID and the path of the package in the repository, of a `.cargo_vcs_info.json`.
+fn read_vcs_info(vcs_info_path: &Path) -> (git::CommitId, PathBuf) {
+    let data = fs::read_to_string(vcs_info_path).unwrap_or_else(|mut err| inconclusive(&mut err));
+    let vcs: JsonValue = data
+        .parse()
+        .unwrap_or_else(|mut err| inconclusive(&mut err));
+    let commit_id = vcs
+        .get_key("git")
+        .unwrap_or_else(|| inconclusive(&mut "VCS does not contain a git section."))
+        .get_key("sha1")
+        .unwrap_or_else(|| inconclusive(&mut "VCS commit ID not recognized."))
+        .get::<String>()
+        .map(|id| git::CommitId::from(&**id))
+        .unwrap_or_else(|| inconclusive(&mut "VCS commit ID is not a string"));
+
+    // Older versions of cargo only packaged crates at the root of their repository.
+    let path_in_vcs = match vcs.get_key("path_in_vcs") {
+        None => PathBuf::new(),
+        Some(path) => path
+            .get::<String>()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| inconclusive(&mut "VCS path of the package is not a string")),
+    };
+
+    (commit_id, path_in_vcs)
+}
+
 #[doc(hidden)]
 pub fn _setup(options: EnvOptions) -> Setup<'static> {
     let EnvOptions {
-        pkg_repository: repository,
-        manifest_dir: manifest,
-        target_tmpdir: tmpdir,
+        pkg_name,
+        pkg_repository,
+        manifest_dir,
+        target_tmpdir,
     } = options;
-    if repository.is_empty() {
-        inconclusive(&mut "The crate must have a valid URL in `package.repository`");
+
+    let mut builder = Setup::builder()
+        .package(pkg_name)
+        .manifest_dir(manifest_dir);
+    // Unlike an explicit repository, the one of the manifest can be overridden at runtime.
+    builder.package_repository = Some(OsString::from(pkg_repository));
+    if let Some(tmpdir) = target_tmpdir {
+        builder = builder.tmpdir(tmpdir);
     }
 
-    // Now allow the override.
-    let repository = OsString::from(repository);
+    builder.build()
+}
 
-    // Make sure this is an integration test, or at least we have the dir.
-    // We don't want to block building over this (e.g. the crate itself here) but we _do_ want to
-    // restrict running this `setup` function
-    let integration_test_tempdir = tmpdir.map(Path::new);
+/// A builder for a [`Setup`] with explicit options, for custom test harnesses.
+///
+/// The [`setup!`] macro fills in the options from the environment that cargo provides to the
+/// integration test being compiled. A harness that is not compiled as such a test, for instance
+/// one that runs the tests of another package, provides them here instead. Any option that is not
+/// set falls back to its environment variable, as documented for packagers. The result is checked
+/// in the same way as with the macro.
+///
+/// # Example
+///
+/// ```
+/// use xtest_data::Setup;
+///
+/// let mut setup = Setup::builder()
+///     .manifest_dir(env!("CARGO_MANIFEST_DIR"))
+///     .repository(env!("CARGO_PKG_REPOSITORY"))
+///     .tmpdir(std::env::temp_dir())
+///     .build();
+/// let datazip = setup.add("tests/data.zip");
+/// let testdata = setup.build();
+///
+/// assert!(testdata.path(&datazip).exists());
+/// ```
+#[must_use = "This is only a builder. Call `build` to create the `Setup`."]
+#[derive(Debug, Default)]
+pub struct SetupBuilder {
+    package: Option<String>,
+    /// The repository of the manifest, which the environment may override.
+    package_repository: Option<OsString>,
+    repository: Option<OsString>,
+    manifest_dir: Option<PathBuf>,
+    tmpdir: Option<PathBuf>,
+    vcs_info: Option<PathBuf>,
+    pack_objects: Option<PathBuf>,
+    environment: Option<bool>,
+    git_dir: Option<PathBuf>,
+    read_only: bool,
+    allow_dirty: Option<bool>,
+    bless: Option<bool>,
+}
 
-    let vcs_info_path = env::var_os("CARGO_XTEST_VCS_INFO");
-    let force_vcs = vcs_info_path.is_some();
+impl SetupBuilder {
+    /// The name of the package, as in `package.name`.
+    ///
+    /// This selects the crate specific environment variables, such as
+    /// `CARGO_XTEST_DATA_PACK_OBJECTS_<NAME>`.
+    pub fn package(mut self, name: impl Into<String>) -> Self {
+        self.package = Some(name.into());
+        self
+    }
 
-    let vcs_info_path = vcs_info_path.as_ref().map_or_else(
-        || Path::new(manifest).join(".cargo_vcs_info.json"),
-        PathBuf::from,
-    );
+    /// The URL of the repository with the data, as in `package.repository`.
+    ///
+    /// Without it, `CARGO_XTEST_DATA_REPOSITORY` must be set.
+    pub fn repository(mut self, url: impl Into<OsString>) -> Self {
+        self.repository = Some(url.into());
+        self
+    }
+
+    /// The directory of the package manifest. Required.
+    ///
+    /// This is the directory that registered paths are relative to. It is either within a
+    /// checkout of the repository, or an unpacked crate archive.
+    pub fn manifest_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.manifest_dir = Some(dir.into());
+        self
+    }
+
+    /// A directory for the shallow repository and checkouts, such as `CARGO_TARGET_TMPDIR`.
+    ///
+    /// Defaults to `CARGO_XTEST_DATA_TMPDIR`, then `TMPDIR`.
+    pub fn tmpdir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.tmpdir = Some(dir.into());
+        self
+    }
+
+    /// The `.cargo_vcs_info.json` of a crate archive, to use its data even without the archive.
+    ///
+    /// Setting this requires the file to exist, like `CARGO_XTEST_VCS_INFO`. Otherwise the file
+    /// is looked for in the manifest directory.
+    pub fn vcs_info(mut self, path: impl Into<PathBuf>) -> Self {
+        self.vcs_info = Some(path.into());
+        self
+    }
+
+    /// The directory of pack objects to write, or read in a crate archive.
+    ///
+    /// Defaults to `CARGO_XTEST_DATA_PACK_OBJECTS_<NAME>`, then `CARGO_XTEST_DATA_PACK_OBJECTS`.
+    pub fn pack_objects(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.pack_objects = Some(dir.into());
+        self
+    }
+
+    /// Whether the variables of the environment configure the setup.
+    ///
+    /// Defaults to `true`. When disabled, the `CARGO_XTEST_DATA_*` variables and
+    /// `CARGO_XTEST_VCS_INFO`, which a packager sets for the tests of a crate, are ignored. Only
+    /// the options of this builder and the manifest of a crate archive select the data. A harness
+    /// that sets up data of other packages than the one under test uses this.
+    pub fn environment(mut self, environment: bool) -> Self {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// A local clone of the repository to take objects from, instead of pack objects.
+    ///
+    /// Defaults to `CARGO_XTEST_DATA_GIT_DIR`.
+    pub fn git_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.git_dir = Some(dir.into());
+        self
+    }
+
+    /// Mark the data read-only, see [`Setup::read_only()`].
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Pack the committed state of modified data with a warning, instead of failing.
+    ///
+    /// Defaults to `CARGO_XTEST_DATA_ALLOW_DIRTY`.
+    pub fn allow_dirty(mut self, allow: bool) -> Self {
+        self.allow_dirty = Some(allow);
+        self
+    }
+
+    /// Write the actual output of a mismatched golden file, see [`FsData::assert_matches()`].
+    ///
+    /// Defaults to `XTEST_DATA_BLESS`.
+    pub fn bless(mut self, bless: bool) -> Self {
+        self.bless = Some(bless);
+        self
+    }
+
+    /// Like [`SetupBuilder::build()`], but return an error when the data can not be accessed.
+    ///
+    /// Panics on invalid arguments are not turned into errors.
+    pub fn try_build(self) -> Result<Setup<'static>, Error> {
+        fallible(|| self.build())
+    }
+
+    /// Determine where the data comes from, and create the [`Setup`].
+    ///
+    /// # Panics
+    ///
+    /// This panics in the same situations as [`setup!`], and if no manifest directory was given.
+    /// See [`SetupBuilder::try_build()`] for a variant that returns an error instead.
+    pub fn build(self) -> Setup<'static> {
+        let SetupBuilder {
+            package,
+            package_repository,
+            repository,
+            manifest_dir,
+            tmpdir,
+            vcs_info,
+            pack_objects: explicit_pack_objects,
+            environment,
+            git_dir: explicit_git_dir,
+            read_only,
+            allow_dirty,
+            bless,
+        } = self;
+
+        // The variables of packagers, unless the caller decides everything.
+        let environment = environment.unwrap_or(true);
+        let var = |name: &str| environment.then(|| env::var_os(name)).flatten();
 
-    let (source, pack_objects);
-    if vcs_info_path.exists() {
-        // Allow the override.
-        trait GetKey {
-            fn get_key(&self, key: &str) -> Option<&Self>;
+        let manifest = manifest_dir.unwrap_or_else(|| {
+            inconclusive(&mut "The setup requires the manifest directory of the package")
+        });
+
+        if package_repository
+            .as_ref()
+            .is_some_and(|url| url.is_empty())
+        {
+            inconclusive(&mut "The crate must have a valid URL in `package.repository`");
         }
-        impl GetKey for JsonValue {
-            fn get_key(&self, key: &str) -> Option<&Self> {
-                self.get::<std::collections::HashMap<_, _>>()?.get(key)
+
+        let repository = match (repository, var("CARGO_XTEST_DATA_REPOSITORY")) {
+            (Some(repository), _) => repository,
+            (None, Some(overridden)) => {
+                if let Some(repository) = &package_repository {
+                    eprintln!(
+                        "xtest-data: Repository overridden by CARGO_XTEST_DATA_REPOSITORY: {} (instead of {})",
+                        Path::new(&overridden).display(),
+                        Path::new(repository).display(),
+                    );
+                }
+                overridden
             }
-        }
+            (None, None) => package_repository.unwrap_or_default(),
+        };
 
-        let data =
-            fs::read_to_string(vcs_info_path).unwrap_or_else(|mut err| inconclusive(&mut err));
-        let vcs: JsonValue = data
-            .parse()
-            .unwrap_or_else(|mut err| inconclusive(&mut err));
-        let commit_id = vcs
-            .get_key("git")
-            .unwrap_or_else(|| inconclusive(&mut "VCS does not contain a git section."))
-            .get_key("sha1")
-            .unwrap_or_else(|| inconclusive(&mut "VCS commit ID not recognized."))
-            .get::<String>()
-            .map(|id| git::CommitId::from(&**id))
-            .unwrap_or_else(|| inconclusive(&mut "VCS commit ID is not a string"));
-
-        // Okay, that makes sense. We know _what_ to access.
-        // Now let's also try to find out how we will access it. Let's find `git`.
-        // To shell out to because we are lazy.
-        let git = git::Git::new().unwrap_or_else(|mut err| inconclusive(&mut err));
-
-        let datadir = integration_test_tempdir
-            .map(Cow::Borrowed)
-            .or_else(|| {
-                    let environment_temp = std::env::var_os("CARGO_XTEST_DATA_TMPDIR")
-                        .or_else(|| std::env::var_os("TMPDIR"))
-                        .map(PathBuf::from)?;
-                    // TODO: nah, in this case we should have some distinguisher for the exact crate
-                    // name and version in the tmpdir. At least that would catch the gravest of errors
-                    // when testing many crates at the same time. (Although sharing the git dir would
-                    // be an advantage).
-                    Some(Cow::Owned(environment_temp))
-                })
-            .expect("This setup must only be called in an integration test or benchmark, or with an explicit TMPDIR")
-            .into_owned();
-
-        pack_objects = std::env::var_os("CARGO_XTEST_DATA_PACK_OBJECTS");
-        source = Source::VcsFromManifest {
-            commit_id,
-            git,
-            datadir,
+        // The data may come from a different commit than the code, e.g. to bisect the data.
+        let commit_override = var("CARGO_XTEST_DATA_COMMIT").map(|commit| {
+            let commit = commit.to_str().unwrap_or_else(|| {
+                inconclusive(&mut "The commit in CARGO_XTEST_DATA_COMMIT is not valid UTF-8")
+            });
+            git::CommitId::from(commit)
+        });
+
+        // Make sure this is an integration test, or at least we have the dir.
+        // We don't want to block building over this (e.g. the crate itself here) but we _do_ want
+        // to restrict running this `setup` function
+        let tmpdir = tmpdir.or_else(|| {
+            // TODO: nah, in this case we should have some distinguisher for the exact crate
+            // name and version in the tmpdir. At least that would catch the gravest of errors
+            // when testing many crates at the same time. (Although sharing the git dir would
+            // be an advantage).
+            var("CARGO_XTEST_DATA_TMPDIR")
+                .or_else(|| std::env::var_os("TMPDIR"))
+                .map(PathBuf::from)
+        });
+
+        let vcs_info_path = vcs_info.or_else(|| var("CARGO_XTEST_VCS_INFO").map(PathBuf::from));
+        let force_vcs = vcs_info_path.is_some();
+        let vcs_info_path = vcs_info_path.unwrap_or_else(|| manifest.join(".cargo_vcs_info.json"));
+
+        let pack_objects_default = || {
+            explicit_pack_objects.map(OsString::from).or_else(|| {
+                environment
+                    .then(|| pack_objects_var(package.as_deref()))
+                    .flatten()
+            })
         };
-    } else if force_vcs {
-        inconclusive(&mut format!(
-            "Expected VCS info at {}",
-            vcs_info_path.display()
-        ));
-    } else {
-        // Check that we can recognize tracked files.
-        let git = git::Git::new().unwrap_or_else(|mut err| inconclusive(&mut err));
-        source = Source::Local(git);
-        pack_objects = std::env::var_os("CARGO_XTEST_DATA_PACK_OBJECTS");
-    };
 
-    // And finally this must be valid.
-    if repository.is_empty() {
-        inconclusive(&mut "The repository must have a valid URL");
+        let (source, pack_objects, prefix);
+        let mut git_dir = None;
+        if let Some(root) = var("CARGO_XTEST_DATA_TREE") {
+            // The data was prepared on the host, we must not assume to be able to run `git`.
+            let materialized = tree::Materialized::read(PathBuf::from(root));
+
+            let (packaged, path_in_vcs) = if vcs_info_path.exists() {
+                let (commit_id, path_in_vcs) = read_vcs_info(&vcs_info_path);
+                (Some(commit_id), path_in_vcs)
+            } else {
+                (None, PathBuf::new())
+            };
+
+            let expected = match commit_override {
+                Some(commit_id) => Some((commit_id, "CARGO_XTEST_DATA_COMMIT selects")),
+                None => packaged.map(|commit_id| (commit_id, "the crate was packaged from")),
+            };
+
+            if let Some((commit_id, expected_by)) = expected {
+                if commit_id != materialized.commit_id {
+                    inconclusive(&mut format!(
+                        "The data tree was materialized from commit {}, but {} {}",
+                        materialized.commit_id, expected_by, commit_id,
+                    ));
+                }
+            }
+
+            pack_objects = None;
+            prefix = Some(path_in_vcs);
+            source = Source::Materialized(materialized);
+        } else if vcs_info_path.exists() {
+            let (packaged, path_in_vcs) = read_vcs_info(&vcs_info_path);
+            let commit_id = match commit_override {
+                Some(commit_id) => {
+                    eprintln!(
+                        "xtest-data: Commit overridden by CARGO_XTEST_DATA_COMMIT: {} (the crate was packaged from {})",
+                        commit_id, packaged,
+                    );
+                    commit_id
+                }
+                None => packaged.clone(),
+            };
+            let crate_matches = commit_id == packaged;
+
+            // Okay, that makes sense. We know _what_ to access.
+            // Now let's also try to find out how we will access it. Let's find `git`.
+            // To shell out to because we are lazy.
+            let git = git::Git::new().unwrap_or_else(|mut err| inconclusive(&mut err));
+
+            let datadir = tmpdir
+                .clone()
+                .expect("This setup must only be called in an integration test or benchmark, or with an explicit TMPDIR");
+
+            // Where the author suggests to fetch the pack objects to, when they are there.
+            pack_objects = pack_objects_default().or_else(|| {
+                let meta = metadata::Metadata::read(&manifest)?;
+                let dir = manifest.join(meta.pack_objects()?);
+                dir.is_dir().then(|| dir.into_os_string())
+            });
+            git_dir =
+                explicit_git_dir.or_else(|| var("CARGO_XTEST_DATA_GIT_DIR").map(PathBuf::from));
+            prefix = Some(path_in_vcs);
+            source = Source::VcsFromManifest {
+                commit_id,
+                crate_matches,
+                git,
+                datadir,
+                vcs_info: force_vcs.then(|| vcs_info_path.clone()),
+            };
+        } else if force_vcs {
+            inconclusive(&mut format!(
+                "Expected VCS info at {}",
+                vcs_info_path.display()
+            ));
+        } else {
+            if commit_override.is_some() {
+                eprintln!("xtest-data: WARNING: CARGO_XTEST_DATA_COMMIT is ignored, local data is used from the working tree");
+            }
+
+            // Check that we can recognize tracked files.
+            (source, prefix) = match git::Git::new() {
+                Ok(git) if git.is_work_tree(&manifest) => {
+                    let prefix = git.show_prefix(&manifest);
+                    (Source::Local(git), Some(prefix))
+                }
+                // A source download or vendored copy. Still useful to run, but we can not vouch for it.
+                _ => {
+                    // Every test sets up its data, but one warning per test binary is enough.
+                    static WARNED: std::sync::Once = std::sync::Once::new();
+                    WARNED.call_once(|| {
+                        eprintln!("xtest-data: WARNING: {} is not in a git repository, test data is used as-is and is not checked against any commit", manifest.display());
+                    });
+                    (Source::Untracked, None)
+                }
+            };
+            pack_objects = pack_objects_default();
+        };
+
+        let allow_dirty =
+            allow_dirty.unwrap_or_else(|| match var("CARGO_XTEST_DATA_ALLOW_DIRTY") {
+                Some(allow) => !allow.is_empty() && allow != "0",
+                None => false,
+            });
+        let dirty = if allow_dirty {
+            DirtyPolicy::Warn
+        } else {
+            DirtyPolicy::Error
+        };
+        let bless = bless.unwrap_or_else(|| {
+            env::var_os("XTEST_DATA_BLESS").is_some_and(|bless| !bless.is_empty() && bless != "0")
+        });
+
+        // And finally this must be valid.
+        if repository.is_empty() {
+            inconclusive(&mut "The repository must have a valid URL");
+        }
+
+        Setup {
+            repository,
+            manifest,
+            prefix,
+            source,
+            resources: Resources::default(),
+            pack_objects,
+            git_dir,
+            tmpdir,
+            read_only,
+            dirty,
+            bless,
+        }
     }
+}
 
-    Setup {
-        repository,
-        manifest,
-        source,
-        resources: Resources::default(),
-        pack_objects,
+impl Setup<'static> {
+    /// Configure a setup explicitly, instead of with [`setup!`].
+    ///
+    /// See [`SetupBuilder`] for the options.
+    pub fn builder() -> SetupBuilder {
+        SetupBuilder::default()
     }
 }
 
@@ -335,7 +958,13 @@ impl<'lt> Setup<'lt> {
     /// paths will be sparsely checked out (meaning: only that path will be downloaded from the VCS
     /// working dir and you can't expect any other files to be present).
     ///
-    /// Those actions will happen when you call [`Setup::build()`].
+    /// Those actions will happen when you call [`Setup::build()`]. Until then, each path already
+    /// holds its normalized form relative to the root of the repository, not the path you passed
+    /// in. Do not use it before the build.
+    ///
+    /// ## Panics
+    ///
+    /// Like [`Setup::add()`], this panics if a path is absolute or outside the repository.
     ///
     /// # Example
     ///
@@ -348,8 +977,27 @@ impl<'lt> Setup<'lt> {
     ///
     /// assert!(path.exists(), "{}", path.display());
     /// ```
+    #[track_caller]
     pub fn rewrite(mut self, iter: impl IntoIterator<Item = &'lt mut PathBuf>) -> Self {
-        self.resources.unmanaged.extend(iter);
+        for item in iter {
+            // Until the rewrite, these are the paths for the VCS.
+            let (registered, vcs) = self.normalize(item);
+            *item = vcs;
+            self.resources.unmanaged.push(item);
+            self.resources.unmanaged_registered.push(registered);
+        }
+        self
+    }
+
+    /// Mark all registered data read-only after it has been materialized.
+    ///
+    /// With this option, tests that accidentally write to their data fail loudly instead of
+    /// changing the data for other tests. Use [`FsData::scratch_copy()`] for data that a test
+    /// needs to modify. Note that in a local checkout, this changes the permissions of the files
+    /// in your working tree. Git does not track the write permission, so these files are not
+    /// considered modified. Directories stay writable so that the data can still be removed.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
         self
     }
 
@@ -358,6 +1006,15 @@ impl<'lt> Setup<'lt> {
     /// The return value is a key that can later be used in [`FsData`]. All the files under this
     /// location will be checked out when `Setup::build()` is called in a crate-build.
     ///
+    /// The path is relative to the manifest. It may refer to data elsewhere in the repository with
+    /// `..` components, for example from a crate in a workspace to a shared data directory. Such
+    /// paths are normalized, and [`FsData::resolve()`] accepts either spelling.
+    ///
+    /// ## Panics
+    ///
+    /// This panics if the path is absolute, or if it points outside of the repository. Neither
+    /// could be found in the commit of a crate archive.
+    ///
     /// # Example
     ///
     /// ```
@@ -368,19 +1025,107 @@ impl<'lt> Setup<'lt> {
     /// let path = testdata.path(&datazip);
     /// assert!(path.exists(), "{}", path.display());
     /// ```
-
+    #[track_caller]
     pub fn add(&mut self, path: impl AsRef<Path>) -> Files {
-        fn path_impl(resources: &mut Resources, path: &Path) -> usize {
-            let item = Managed::Files(path.to_owned());
-            let key = resources.relative_files.len();
-            resources.relative_files.push(item);
-            key
+        let (registered, vcs) = self.normalize(path.as_ref());
+        let key = self.resources.relative_files.len();
+        self.resources.relative_files.push(Managed::Files(vcs));
+        self.resources.registered.push(registered);
+        Files { key }
+    }
+
+    /// Register a tree of files, except for some paths below it.
+    ///
+    /// This works like [`Setup::add()`], but the excluded files and trees are not checked out for
+    /// a crate archive, nor packed and checked for local modifications in the repository. The
+    /// excluded paths are relative to the manifest as well, and must be strictly below `path`.
+    /// Note that in your local repository, the excluded files are still present in the tree. Do
+    /// not rely on their absence.
+    ///
+    /// Data that is registered elsewhere is never left out. If another path overlaps with an
+    /// exclusion, then that exclusion is ignored.
+    ///
+    /// ## Panics
+    ///
+    /// Like [`Setup::add()`], and if an excluded path is not below `path`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut vcs = xtest_data::setup!();
+    /// let cases = vcs.add_excluding("tests/cases", ["tests/cases/b.txt"]);
+    /// let testdata = vcs.build();
+    ///
+    /// assert!(testdata.path(&cases).join("a.txt").exists());
+    /// ```
+    #[track_caller]
+    pub fn add_excluding(
+        &mut self,
+        path: impl AsRef<Path>,
+        excludes: impl IntoIterator<Item = impl AsRef<Path>>,
+    ) -> Files {
+        let (registered, vcs) = self.normalize(path.as_ref());
+
+        let mut excluded = vec![];
+        for exclude in excludes {
+            let exclude = exclude.as_ref();
+            let (_, exclude_vcs) = self.normalize(exclude);
+            if exclude_vcs == vcs || !exclude_vcs.starts_with(&vcs) {
+                inconclusive(&mut format!(
+                    "The excluded path {} is not below {}",
+                    exclude.display(),
+                    path.as_ref().display()
+                ));
+            }
+
+            excluded.push(exclude_vcs);
         }
 
-        let key = path_impl(&mut self.resources, path.as_ref());
+        let key = self.resources.relative_files.len();
+        self.resources.relative_files.push(Managed::Excluding {
+            path: vcs,
+            excludes: excluded,
+        });
+        self.resources.registered.push(registered);
         Files { key }
     }
 
+    /// Validate a path relative to the manifest, and find the path relative to the repository.
+    ///
+    /// Returns the normalized path relative to the manifest, and the one relative to the root of
+    /// the repository. Without any repository, these are the same.
+    #[track_caller]
+    fn normalize(&self, path: &Path) -> (PathBuf, PathBuf) {
+        let registered = match normalize_path(path) {
+            Some(registered) => registered,
+            None => inconclusive(&mut format!(
+                "Test data must be relative to the manifest, not an absolute path: {}",
+                path.display()
+            )),
+        };
+
+        let prefix = match &self.prefix {
+            Some(prefix) => prefix,
+            None => return (registered.clone(), registered),
+        };
+
+        match normalize_path(&prefix.join(&registered)) {
+            Some(vcs) if !vcs.starts_with("..") => (registered, vcs),
+            _ => inconclusive(&mut format!(
+                "Test data must be within the repository, but {} points outside of it",
+                path.display()
+            )),
+        }
+    }
+
+    /// Like [`Setup::build()`], but return an error when the data can not be validated or
+    /// retrieved.
+    ///
+    /// Panics on invalid arguments are not turned into errors.
+    pub fn try_build(self) -> Result<FsData, Error> {
+        fallible(|| self.build())
+    }
+
     /// Run the final validation and perform rewrites.
     ///
     /// Returns the frozen dictionary of file mappings that had been registered with
@@ -392,67 +1137,552 @@ impl<'lt> Setup<'lt> {
     /// * Any registered file or tree is not tracked in the VCS.
     /// * You have not allowed retrieving data from the VCS.
     /// * It was not possible to retrieve the data from the VCS.
+    ///
+    /// See [`Setup::try_build()`] for a variant that returns these failures as an error.
     pub fn build(self) -> FsData {
+        let start = std::time::Instant::now();
+        let (git_count, git_time) = log::git_stats();
+        let summary = log::enabled(log::Level::Info).then(|| self.describe());
+
         let mut map;
+        let rewritten: Vec<PathBuf>;
+        let tree;
+        let mut checkout = None;
+        let registered = self.resources.registered.clone();
+        let unmanaged_registered = self.resources.unmanaged_registered.clone();
+        let excludes = self.resources.excludes();
+        let vcs_paths = self
+            .resources
+            .relative_files
+            .iter()
+            .map(|path| path.as_path().to_owned())
+            .collect();
         match self.source {
             Source::Local(git) => {
-                let dir = git::CrateDir::new(self.manifest, &git);
-                let datapath = Path::new(self.manifest);
-                dir.tracked(&git, &mut self.resources.path_specs());
+                let dir = git::CrateDir::new(&self.manifest);
+                let datapath = repository_root(&self.manifest, self.prefix.as_deref());
+
+                let paths: Vec<PathBuf> = self
+                    .resources
+                    .path_specs()
+                    .filter_map(|spec| spec.as_encompassing_path().map(Path::to_owned))
+                    .collect();
+                let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+                let excludes: Vec<&Path> = excludes.iter().map(PathBuf::as_path).collect();
+
+                // Paths within a submodule are not known to the superproject, we ask the submodule.
+                let submodules = dir.submodules(&git, &paths);
+                let in_submodule =
+                    |path: &Path| submodules.iter().any(|sub| path.starts_with(&sub.path));
+                let outer: Vec<&Path> =
+                    paths.iter().copied().filter(|p| !in_submodule(p)).collect();
+                let outer_excludes: Vec<&Path> = excludes
+                    .iter()
+                    .copied()
+                    .filter(|p| !in_submodule(p))
+                    .collect();
+
+                // Nothing changed since the paths were last found clean at the same commit. Then we
+                // need not ask git about the state of every file again. The marker is kept in our
+                // temporary directory, and holds a fingerprint of the files and of the index.
+                let head = dir.head(&git);
+                let start = std::time::SystemTime::now();
+                let clean =
+                    self.tmpdir
+                        .as_deref()
+                        .zip(dir.index_file())
+                        .and_then(|(tmpdir, index)| {
+                            let marker = dir.clean_marker(
+                                &tmpdir.join("xtest-data-clean"),
+                                &head,
+                                &mut pathspecs(&outer, &outer_excludes),
+                            );
+                            let (fingerprint, newest) = fingerprint(&datapath, &index, &outer)?;
+                            Some((marker, index, fingerprint, newest))
+                        });
+                let unchanged = clean.as_ref().is_some_and(|(marker, _, fingerprint, _)| {
+                    fs::read_to_string(marker).is_ok_and(|memo| memo == *fingerprint)
+                });
+
+                let mut dirty = if unchanged {
+                    vec![]
+                } else {
+                    let dirty = dir.tracked(&git, &mut pathspecs(&outer, &outer_excludes));
+                    if let (true, Some((marker, index, before, newest))) = (dirty.is_empty(), clean)
+                    {
+                        // Only a memo, a failure means that we check again next time.
+                        let after = fingerprint(&datapath, &index, &outer);
+                        if after.is_some_and(|(after, _)| after == before) && !racy(newest, start) {
+                            let _ = write_marker(&marker, &before);
+                        }
+                    }
+
+                    dirty
+                };
+                let subdirs: Vec<_> = submodules
+                    .iter()
+                    .map(|sub| {
+                        let subdir = dir.submodule(&git, sub);
+                        let inner: Vec<&Path> =
+                            paths.iter().filter_map(|path| sub.relative(path)).collect();
+                        let inner_excludes: Vec<&Path> = excludes
+                            .iter()
+                            .filter_map(|path| path.strip_prefix(&sub.path).ok())
+                            .collect();
+
+                        if subdir.head(&git) != sub.commit {
+                            dirty.push(git::DirtyFile {
+                                kind: git::Dirty::Modified,
+                                path: sub.path.display().to_string(),
+                            });
+                        }
+
+                        let tracked = subdir.tracked(&git, &mut pathspecs(&inner, &inner_excludes));
+                        dirty.extend(tracked.into_iter().map(|mut file| {
+                            file.path = format!("{}/{}", sub.path.display(), file.path);
+                            file
+                        }));
+
+                        (sub, subdir, inner, inner_excludes)
+                    })
+                    .collect();
 
                 if let Some(pack_objects) = self.pack_objects {
+                    // The pack contains the committed state, which is not what we tested here.
+                    if !dirty.is_empty() {
+                        for file in &dirty {
+                            eprintln!("{}", file);
+                        }
+
+                        match self.dirty {
+                            DirtyPolicy::Error => inconclusive(&mut "Your test depends on file(s) that differ from `HEAD`, which is the state that would be packed. Commit them, or set `CARGO_XTEST_DATA_ALLOW_DIRTY=1` to only warn"),
+                            DirtyPolicy::Warn => eprintln!("xtest-data: Packing the committed state of file(s) that differ from `HEAD`"),
+                        }
+                    }
+
+                    // Keyed by commit, so one directory can hold the packs of many crates.
+                    let pack_objects = Path::new(&pack_objects).join(head.to_string());
                     std::fs::create_dir_all(&pack_objects)
                         .unwrap_or_else(|mut err| inconclusive(&mut err));
-                    dir.pack_objects(&git, &mut self.resources.path_specs(), pack_objects);
+                    dir.pack_objects(
+                        &git,
+                        &head,
+                        &mut pathspecs(&outer, &outer_excludes),
+                        pack_objects.clone().into(),
+                    );
+
+                    // The pack has pointers only, the LFS objects are stored next to it.
+                    let pointers = dir.lfs_pointers(&git, &head, &outer, &outer_excludes);
+                    let store = [dir.lfs_objects(&git)];
+                    let lfs_objects = pack_objects.join("lfs").join("objects");
+                    for (name, pointer) in pointers {
+                        let target = pointer.object_path(&lfs_objects);
+                        if target.exists() {
+                            continue;
+                        }
+
+                        let object = pointer.find(&store).unwrap_or_else(|| {
+                            inconclusive(&mut format!(
+                                "The LFS object of {} is not available in {}, run `git lfs fetch`",
+                                name,
+                                store[0].display()
+                            ))
+                        });
+                        pointer
+                            .copy_verified(&object, &target)
+                            .unwrap_or_else(|mut err| inconclusive(&mut err));
+                    }
+
+                    // The objects of submodules go into the same pack directory.
+                    for (sub, subdir, inner, inner_excludes) in &subdirs {
+                        subdir.pack_objects(
+                            &git,
+                            &sub.commit,
+                            &mut pathspecs(inner, inner_excludes),
+                            pack_objects.clone().into(),
+                        );
+                    }
                 }
 
+                drop(subdirs);
+
                 map = vec![];
                 self.resources.relative_files.iter().for_each(|path| {
                     map.push(datapath.join(path.as_path()));
                 });
 
-                self.resources
+                rewritten = self
+                    .resources
                     .unmanaged
                     .into_iter()
-                    .for_each(|item| set_root(datapath, item));
-            }
-            Source::VcsFromManifest {
-                commit_id,
-                datadir,
-                git,
-            } => {
-                let origin = git::Origin {
-                    url: self.repository,
+                    .map(|item| set_root(&datapath, item))
+                    .collect();
+                tree = Tree::Local {
+                    git,
+                    dir,
+                    submodules,
                 };
+            }
+            Source::Untracked => {
+                if self.pack_objects.is_some() {
+                    inconclusive(&mut "Can not create pack objects without a git repository");
+                }
 
-                let gitpath = datadir.join("xtest-data-git");
-                let datapath = unique_dir(&datadir, "xtest-data-tree")
-                    .unwrap_or_else(|mut err| inconclusive(&mut err));
+                let datapath = self.manifest.as_path();
+                let missing = self
+                    .resources
+                    .path_specs()
+                    .filter_map(|spec| spec.as_encompassing_path().map(|path| datapath.join(path)))
+                    .filter(|path| !path.exists())
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>();
 
-                let shallow;
-                if let Some(pack_objects) = self.pack_objects {
-                    shallow = git.bare(gitpath, &commit_id);
-                    shallow.unpack(&git, &pack_objects);
-                } else {
-                    panic!("Requested test data from {} but have no packed artifacts to load. Provide an explicit path to a directory to unpack via the `CARGO_XTEST_DATA_PACK_OBJECTS` environment variable", Path::new(&origin.url).display());
+                if !missing.is_empty() {
+                    inconclusive(&mut format!(
+                        "Test data does not exist: {}",
+                        missing.join(", ")
+                    ));
                 }
 
-                shallow.checkout(
-                    &git,
-                    &datapath,
-                    &commit_id,
-                    &mut self.resources.path_specs(),
-                );
                 map = vec![];
                 self.resources.relative_files.iter().for_each(|path| {
                     map.push(datapath.join(path.as_path()));
                 });
-                self.resources
+                rewritten = self
+                    .resources
                     .unmanaged
                     .into_iter()
-                    .for_each(|item| set_root(&datapath, item));
+                    .map(|item| set_root(datapath, item))
+                    .collect();
+                tree = Tree::Files;
             }
-        }
+            Source::Materialized(materialized) => {
+                let datapath = &materialized.root;
+                let missing = self
+                    .resources
+                    .path_specs()
+                    .filter_map(|spec| spec.as_encompassing_path().map(|path| datapath.join(path)))
+                    .filter(|path| !path.exists())
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>();
+
+                if !missing.is_empty() {
+                    inconclusive(&mut format!(
+                        "Test data was not materialized in the data tree: {}",
+                        missing.join(", ")
+                    ));
+                }
+
+                map = vec![];
+                self.resources.relative_files.iter().for_each(|path| {
+                    map.push(datapath.join(path.as_path()));
+                });
+                rewritten = self
+                    .resources
+                    .unmanaged
+                    .into_iter()
+                    .map(|item| set_root(datapath, item))
+                    .collect();
+                tree = Tree::Materialized {
+                    commit_id: materialized.commit_id,
+                    files: materialized.files,
+                };
+            }
+            Source::VcsFromManifest {
+                commit_id,
+                crate_matches,
+                datadir,
+                git,
+                vcs_info,
+            } => {
+                let origin = git::Origin {
+                    url: self.repository,
+                };
+
+                let crate_dir = self.manifest.as_path();
+                // Paths are relative to the repository, which the crate archive is a part of.
+                let prefix = self.prefix.as_deref().unwrap_or(Path::new(""));
+                let crate_root = repository_root(crate_dir, Some(prefix));
+                let gitpath = datadir.join("xtest-data-git");
+
+                let paths: Vec<PathBuf> = self
+                    .resources
+                    .path_specs()
+                    .filter(|spec| spec.as_excluded_path().is_none())
+                    .map(|spec| {
+                        spec.as_encompassing_path()
+                            .expect("Only simple paths")
+                            .to_owned()
+                    })
+                    .collect();
+                let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+                let excludes: Vec<&Path> = excludes.iter().map(PathBuf::as_path).collect();
+                let mut submodules = vec![];
+
+                let shallow = if self.pack_objects.is_some() || self.git_dir.is_some() {
+                    let shallow = git.bare(gitpath, &commit_id);
+
+                    if let Some(git_dir) = &self.git_dir {
+                        shallow.add_alternate(&git, git_dir);
+                    }
+
+                    if let Some(pack_objects) = &self.pack_objects {
+                        shallow.unpack(&git, pack_objects, &commit_id);
+                    }
+
+                    if !shallow.has_commit(&git, &commit_id) {
+                        inconclusive(&mut format!(
+                            "The commit {} of the crate is not available from the provided objects{}",
+                            commit_id,
+                            fetch_instructions(crate_dir),
+                        ));
+                    }
+
+                    // The packs also hold the pinned commits of submodules, without history.
+                    submodules = shallow.submodules(&git, &commit_id, &paths);
+                    for sub in &submodules {
+                        shallow.add_shallow(&sub.commit);
+                        if !shallow.has_commit(&git, &sub.commit) {
+                            inconclusive(&mut format!(
+                                "The commit {} of the submodule {} is not available from the provided objects",
+                                sub.commit,
+                                sub.path.display()
+                            ));
+                        }
+                    }
+
+                    Some(shallow)
+                } else {
+                    None
+                };
+
+                // Data that is included in the crate archive does not need to be fetched. If we
+                // have the commit's objects we can also verify that it is in fact the same data.
+                let from_crate: Vec<bool> = paths
+                    .iter()
+                    .map(|&path| {
+                        if !path.starts_with(prefix) || !crate_root.join(path).exists() {
+                            return false;
+                        }
+
+                        // The commit of the crate does not have the blobs of submodules to compare.
+                        if submodules.iter().any(|sub| sub.relative(path).is_some()) {
+                            return false;
+                        }
+
+                        let spec = git::PathSpec::Path(path);
+
+                        match &shallow {
+                            // Without any objects, we can only trust the crate for its own commit.
+                            // Even then, cargo may have packaged only some files of a directory.
+                            None => crate_matches && crate_root.join(path).is_file(),
+                            Some(shallow)
+                                if shallow.verify_files(
+                                    &git,
+                                    &commit_id,
+                                    &crate_root,
+                                    &spec,
+                                    &excludes,
+                                ) =>
+                            {
+                                true
+                            }
+                            Some(_) => {
+                                eprintln!(
+                                    "xtest-data: `{}` in the crate differs from commit {}",
+                                    path.display(),
+                                    commit_id
+                                );
+                                false
+                            }
+                        }
+                    })
+                    .collect();
+
+                let missing: Vec<&Path> = paths
+                    .iter()
+                    .zip(&from_crate)
+                    .filter(|(_, &from_crate)| !from_crate)
+                    .map(|(&path, _)| path)
+                    .collect();
+
+                let datapath = if !missing.is_empty() {
+                    let shallow = shallow.as_ref().unwrap_or_else(|| {
+                        fail(format!("Requested test data from {} but have no packed artifacts to load. Provide an explicit path to a directory to unpack via the `CARGO_XTEST_DATA_PACK_OBJECTS` environment variable, or a local clone via `CARGO_XTEST_DATA_GIT_DIR`{}", Path::new(&origin.url).display(), fetch_instructions(crate_dir)));
+                    });
+
+                    let datapath = unique_dir(&datadir, "xtest-data-tree")
+                        .unwrap_or_else(|mut err| inconclusive(&mut err));
+                    let in_submodule =
+                        |path: &Path| submodules.iter().any(|sub| path.starts_with(&sub.path));
+                    let outer: Vec<&Path> = missing
+                        .iter()
+                        .copied()
+                        .filter(|p| !in_submodule(p))
+                        .collect();
+                    let outer_excludes: Vec<&Path> = excludes
+                        .iter()
+                        .copied()
+                        .filter(|p| !in_submodule(p))
+                        .collect();
+                    shallow.checkout(
+                        &git,
+                        &datapath,
+                        &commit_id,
+                        &mut pathspecs(&outer, &outer_excludes),
+                    );
+
+                    // Checked out as pointers, replace them with the objects we were given.
+                    let pointers = shallow.lfs_pointers(&git, &commit_id, &outer, &outer_excludes);
+                    if !pointers.is_empty() {
+                        let mut stores = vec![];
+                        if let Some(pack_objects) = &self.pack_objects {
+                            let root = Path::new(pack_objects);
+                            stores.push(root.join(commit_id.to_string()).join("lfs/objects"));
+                            stores.push(root.join("lfs/objects"));
+                        }
+                        if let Some(git_dir) = &self.git_dir {
+                            stores.push(git.common_dir(git_dir).join("lfs/objects"));
+                        }
+
+                        for (name, pointer) in pointers {
+                            let object = pointer.find(&stores).unwrap_or_else(|| {
+                                inconclusive(&mut format!(
+                                    "The LFS object {} of {} is not in the pack objects",
+                                    pointer.oid, name
+                                ))
+                            });
+                            pointer
+                                .copy_verified(&object, &datapath.join(&name))
+                                .unwrap_or_else(|mut err| inconclusive(&mut err));
+                        }
+                    }
+
+                    // The superproject leaves an empty directory for each submodule.
+                    for sub in &submodules {
+                        let inner: Vec<&Path> = missing
+                            .iter()
+                            .filter_map(|path| sub.relative(path))
+                            .collect();
+                        if inner.is_empty() {
+                            continue;
+                        }
+
+                        let inner_excludes: Vec<&Path> = excludes
+                            .iter()
+                            .filter_map(|path| path.strip_prefix(&sub.path).ok())
+                            .collect();
+                        let worktree = datapath.join(&sub.path);
+                        fs::create_dir_all(&worktree)
+                            .unwrap_or_else(|mut err| inconclusive(&mut err));
+                        shallow.checkout_fallback_slow(
+                            &git,
+                            &worktree,
+                            &sub.commit,
+                            &mut pathspecs(&inner, &inner_excludes),
+                        );
+                        shallow.verify_modes(&git, &sub.commit, &worktree, &inner, &inner_excludes);
+                    }
+
+                    shallow.verify_modes(&git, &commit_id, &datapath, &outer, &outer_excludes);
+
+                    datapath
+                } else {
+                    // Nothing to checkout, everything will be resolved from the crate.
+                    crate_root.clone()
+                };
+
+                if !missing.is_empty() {
+                    let mut environment = vec![];
+                    if let Some(pack_objects) = &self.pack_objects {
+                        environment.push(("CARGO_XTEST_DATA_PACK_OBJECTS", pack_objects.clone()));
+                    }
+                    if let Some(git_dir) = &self.git_dir {
+                        environment.push(("CARGO_XTEST_DATA_GIT_DIR", git_dir.clone().into()));
+                    }
+                    if let Some(vcs_info) = &vcs_info {
+                        environment.push(("CARGO_XTEST_VCS_INFO", vcs_info.clone().into()));
+                    }
+                    if !crate_matches {
+                        environment.push(("CARGO_XTEST_DATA_COMMIT", commit_id.to_string().into()));
+                    }
+
+                    checkout = Some(Checkout {
+                        dir: datapath.clone(),
+                        commit_id: commit_id.clone(),
+                        manifest: crate_dir.to_owned(),
+                        environment,
+                        paths: missing.iter().map(|&path| path.to_owned()).collect(),
+                        complete: missing.len() == paths.len(),
+                        // The test harness names the thread of each test after it.
+                        test: std::thread::current()
+                            .name()
+                            .filter(|&name| name != "main")
+                            .map(String::from),
+                        rewritten: false,
+                    });
+                }
+
+                drop(missing);
+                drop(paths);
+                let mut from_crate = from_crate.into_iter();
+                let mut resolve = |path: &Path| {
+                    let (root, source) = if from_crate.next().unwrap() {
+                        (crate_root.as_path(), "the crate archive")
+                    } else {
+                        (datapath.as_path(), "the repository")
+                    };
+
+                    log::info(format_args!("`{}` from {}", path.display(), source));
+                    root
+                };
+
+                map = vec![];
+                self.resources.relative_files.iter().for_each(|path| {
+                    map.push(resolve(path.as_path()).join(path.as_path()));
+                });
+                rewritten = self
+                    .resources
+                    .unmanaged
+                    .into_iter()
+                    .map(|item| {
+                        let root = resolve(item);
+                        set_root(root, item)
+                    })
+                    .collect();
+
+                if let Some(checkout) = &mut checkout {
+                    checkout.rewritten = rewritten.iter().any(|path| path.starts_with(&datapath));
+                }
+
+                tree = match shallow {
+                    Some(shallow) => Tree::Vcs {
+                        git,
+                        repo: shallow,
+                        commit_id,
+                        submodules,
+                    },
+                    None => Tree::Files,
+                };
+            }
+        }
+
+        if self.read_only {
+            map.iter().chain(&rewritten).for_each(|path| {
+                set_read_only(path).unwrap_or_else(|mut err| inconclusive(&mut err))
+            });
+        }
+
+        if let Some(summary) = summary {
+            let (count, time) = log::git_stats();
+            log::info(format_args!(
+                "{}, built in {:.2?} with {} git invocation(s) taking {:.2?}",
+                summary,
+                start.elapsed(),
+                count - git_count,
+                time - git_time,
+            ));
+        }
 
         // In the end we just discard some information.
         // We don't really need it anymore after the checks.
@@ -460,15 +1690,87 @@ impl<'lt> Setup<'lt> {
         // TODO: of course we could avoid actually checking files onto the disk if we had some kind
         // of `io::Read` abstraction that read them straight from `git cat` instead. But chances
         // are you'll like your files and directory structures.
-        FsData { map }
+        FsData {
+            object_ids: map.iter().map(|_| OnceLock::new()).collect(),
+            map,
+            registered,
+            rewritten: unmanaged_registered.into_iter().zip(rewritten).collect(),
+            vcs_paths,
+            excludes,
+            tmpdir: self.tmpdir,
+            tree,
+            checkout,
+            commit: OnceLock::new(),
+            bless: self.bless,
+        }
+    }
+}
+
+impl Setup<'_> {
+    /// A one-line description of the source and registered data, for the log.
+    fn describe(&self) -> String {
+        let source = match &self.source {
+            Source::Local(_) => "local repository".to_owned(),
+            Source::Untracked => "local files without repository".to_owned(),
+            Source::Materialized(materialized) => format!(
+                "materialized tree {} at commit {}",
+                materialized.root.display(),
+                materialized.commit_id
+            ),
+            Source::VcsFromManifest { commit_id, .. } => format!("crate at commit {}", commit_id),
+        };
+
+        let specs: Vec<String> = self
+            .resources
+            .path_specs()
+            .map(|spec| spec.to_string())
+            .collect();
+
+        format!(
+            "setup from {} in {}, paths [{}]",
+            source,
+            self.manifest.display(),
+            specs.join(", ")
+        )
     }
 }
 
 impl Resources<'_> {
     pub fn path_specs(&self) -> impl Iterator<Item = git::PathSpec<'_>> {
         let values = self.relative_files.iter().map(Managed::as_path_spec);
-        let unmanaged = self.unmanaged.iter().map(|x| git::PathSpec::Path(&**x));
-        values.chain(unmanaged)
+        let unmanaged = self.unmanaged.iter().map(|x| git::PathSpec::Path(x));
+        let excludes = self.relative_files.iter().flat_map(Managed::excludes);
+        let excludes = excludes.filter(move |exclude| self.is_excluded(exclude));
+        values
+            .chain(unmanaged)
+            .chain(excludes.map(|x| git::PathSpec::Exclude(x)))
+    }
+
+    /// The paths that are left out of all registered trees.
+    fn excludes(&self) -> Vec<PathBuf> {
+        self.relative_files
+            .iter()
+            .flat_map(Managed::excludes)
+            .filter(|exclude| self.is_excluded(exclude))
+            .map(PathBuf::clone)
+            .collect()
+    }
+
+    /// Check that an exclusion does not overlap with any other registered path.
+    ///
+    /// Git applies an exclusion to all paths at once. If another path asks for any of the data
+    /// below it, or for all of it, then that data is not left out.
+    fn is_excluded(&self, exclude: &Path) -> bool {
+        let mut paths = self.relative_files.iter().filter_map(|item| match item {
+            Managed::Files(path) => Some(path),
+            Managed::Excluding { path, excludes } => {
+                Some(path).filter(|_| !excludes.iter().any(|other| other == exclude))
+            }
+        });
+
+        let mut unmanaged = self.unmanaged.iter().map(|path| &**path);
+        let overlaps = |path: &PathBuf| path.starts_with(exclude) || exclude.starts_with(path);
+        !paths.any(overlaps) && !unmanaged.any(overlaps)
     }
 }
 
@@ -477,24 +1779,891 @@ impl FsData {
     pub fn path(&self, file: &Files) -> &Path {
         self.map.get(file.key).unwrap().as_path()
     }
+
+    /// Export the resolved paths for a child process, as an environment variable.
+    ///
+    /// Returns the name of the variable, `CARGO_XTEST_DATA_PATHS`, and a JSON array. It has an
+    /// object for each path given to [`Setup::add()`] and then [`Setup::rewrite()`], in order, with
+    /// the path as `registered` and its resolved location as `path`. A process started with this
+    /// variable can recover the same paths with [`FsData::from_env()`], without running `git`
+    /// again. Scripts can read the JSON directly.
+    ///
+    /// ```no_run
+    /// # let mut vcs = xtest_data::setup!();
+    /// # let data = vcs.build();
+    /// let status = std::process::Command::new("path/to/helper")
+    ///     .envs([data.to_env()])
+    ///     .status();
+    /// ```
+    ///
+    /// ## Panics
+    ///
+    /// This will panic if a path is not valid UTF-8.
+    pub fn to_env(&self) -> (&'static str, String) {
+        (PATHS_VAR, self.to_json())
+    }
+
+    /// Export the resolved paths into a JSON file.
+    ///
+    /// Point `CARGO_XTEST_DATA_PATHS` at the file to recover the paths with
+    /// [`FsData::from_env()`]. Prefer this when there are many paths, since the size of the
+    /// environment is limited on some platforms.
+    ///
+    /// ## Panics
+    ///
+    /// This will panic if a path is not valid UTF-8, or if the file can not be written.
+    pub fn write_json(&self, path: impl AsRef<Path>) {
+        fs::write(path, self.to_json()).unwrap_or_else(|mut err| inconclusive(&mut err));
+    }
+
+    /// Recover the paths exported by a parent process with [`FsData::to_env()`].
+    ///
+    /// The variable `CARGO_XTEST_DATA_PATHS` holds either the JSON itself or the path of a file
+    /// written by [`FsData::write_json()`]. Returns `None` if the variable is not set. Retrieve
+    /// the paths with [`FsData::resolve()`].
+    ///
+    /// ## Panics
+    ///
+    /// This will panic if the variable or file does not contain a valid mapping.
+    pub fn from_env() -> Option<FsData> {
+        let value = env::var_os(PATHS_VAR)?;
+        let json = match value.to_str() {
+            Some(json) if json.trim_start().starts_with('[') => json.to_owned(),
+            _ => fs::read_to_string(&value).unwrap_or_else(|mut err| inconclusive(&mut err)),
+        };
+
+        let paths: JsonValue = json
+            .parse()
+            .unwrap_or_else(|mut err| inconclusive(&mut err));
+        let paths: &Vec<JsonValue> = paths
+            .get()
+            .unwrap_or_else(|| inconclusive(&mut "Exported paths must be a JSON array"));
+
+        let (mut registered, mut map) = (vec![], vec![]);
+        for entry in paths {
+            let path = |key: &str| match &entry[key] {
+                JsonValue::String(path) => PathBuf::from(path),
+                _ => {
+                    inconclusive(&mut "Exported paths must have a `registered` and a `path` string")
+                }
+            };
+            registered.push(path("registered"));
+            map.push(path("path"));
+        }
+
+        let tmpdir = env::var_os("CARGO_XTEST_DATA_TMPDIR")
+            .or_else(|| env::var_os("TMPDIR"))
+            .map(PathBuf::from);
+
+        Some(FsData {
+            object_ids: map.iter().map(|_| OnceLock::new()).collect(),
+            map,
+            vcs_paths: registered.clone(),
+            registered,
+            rewritten: vec![],
+            excludes: vec![],
+            tmpdir,
+            tree: Tree::Files,
+            checkout: None,
+            commit: OnceLock::new(),
+            bless: false,
+        })
+    }
+
+    fn to_json(&self) -> String {
+        let as_str = |path: &PathBuf| -> String {
+            path.to_str()
+                .unwrap_or_else(|| {
+                    inconclusive(&mut format!("Path is not valid UTF-8: {}", path.display()))
+                })
+                .to_owned()
+        };
+
+        let paths = self
+            .resolved()
+            .map(|(registered, local)| {
+                let entry = [
+                    (
+                        "registered".to_owned(),
+                        JsonValue::String(as_str(registered)),
+                    ),
+                    ("path".to_owned(), JsonValue::String(as_str(local))),
+                ];
+                JsonValue::Object(std::collections::HashMap::from(entry))
+            })
+            .collect();
+
+        JsonValue::Array(paths)
+            .stringify()
+            .unwrap_or_else(|mut err| inconclusive(&mut err))
+    }
+
+    /// Create a private, writable copy of a file or tree of files.
+    ///
+    /// Each call creates a new unique directory in the temporary directory of the test and copies
+    /// the data into it, keeping the relative path under which it was registered. The returned
+    /// path refers to the copy. Modifications to it do not affect the data seen by other tests, or
+    /// the files in your working tree.
+    ///
+    /// Files are copied with [`std::fs::copy`], which clones them cheaply where the platform and
+    /// file system support reflinks, and copies their content otherwise. There is no fallback to
+    /// hard links: a hard link shares its content and permissions with the original data.
+    ///
+    /// ## Panics
+    ///
+    /// This will panic if there is no temporary directory, i.e. when called outside of an
+    /// integration test without `CARGO_XTEST_DATA_TMPDIR` or `TMPDIR`, or if copying fails.
+    pub fn scratch_copy(&self, file: &Files) -> PathBuf {
+        let tmpdir = self.tmpdir.as_deref().unwrap_or_else(|| {
+            inconclusive(&mut "A scratch copy requires a temporary directory, run in an integration test or set `CARGO_XTEST_DATA_TMPDIR`")
+        });
+
+        let scratch = unique_dir(tmpdir, "xtest-data-scratch")
+            .unwrap_or_else(|mut err| inconclusive(&mut err));
+        // Leading `..` of data outside the manifest must not escape the scratch directory.
+        let relative: PathBuf = self.registered[file.key]
+            .components()
+            .filter(|component| matches!(component, std::path::Component::Normal(_)))
+            .collect();
+        let target = scratch.join(relative);
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).unwrap_or_else(|mut err| inconclusive(&mut err));
+        }
+
+        copy_writable(self.path(file), &target).unwrap_or_else(|mut err| inconclusive(&mut err));
+        target
+    }
+
+    /// Run a test for each file in a registered tree, and report all failures together.
+    ///
+    /// The files are enumerated from the VCS in its deterministic tree order, not from the file
+    /// system. That is, the index of a local repository and the pinned commit in a crate. Once
+    /// committed, the same cases run in both situations. The test is called with the rewritten path of
+    /// each file. A panic in one case does not stop the others from running. Files that were left
+    /// out with [`Setup::add_excluding()`] are skipped.
+    ///
+    /// ## Panics
+    ///
+    /// After all cases have run, this panics if any of them failed, with a list of their paths
+    /// relative to the repository root. It also panics if the tree has no files at all, such that
+    /// a misspelled or empty tree does not silently pass.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut vcs = xtest_data::setup!();
+    /// let corpus = vcs.add("tests/cases");
+    /// let testdata = vcs.build();
+    ///
+    /// testdata.cases(&corpus, |path| {
+    ///     assert!(path.is_file(), "{}", path.display());
+    /// });
+    /// ```
+    pub fn cases(&self, files: &Files, mut test: impl FnMut(&Path)) {
+        let registered = &self.vcs_paths[files.key];
+        let local = self.path(files);
+
+        let in_submodule = |submodules: &[git::Submodule]| {
+            submodules
+                .iter()
+                .any(|sub| sub.relative(registered).is_some())
+        };
+
+        let names = match &self.tree {
+            // Files of submodules are not listed by the superproject. Their checkout is exact.
+            Tree::Local { submodules, .. } | Tree::Vcs { submodules, .. }
+                if in_submodule(submodules) =>
+            {
+                tree::list_files(local, registered).unwrap_or_else(|mut err| inconclusive(&mut err))
+            }
+            Tree::Local { git, dir, .. } => dir.list_files(git, git::PathSpec::Path(registered)),
+            Tree::Vcs {
+                git,
+                repo,
+                commit_id,
+                ..
+            } => repo.list_files(git, commit_id, git::PathSpec::Path(registered)),
+            // Filtered below, all the same.
+            Tree::Materialized { files, .. } => files.clone(),
+            Tree::Files => {
+                tree::list_files(local, registered).unwrap_or_else(|mut err| inconclusive(&mut err))
+            }
+        };
+
+        let excludes: Vec<&Path> = self.excludes.iter().map(PathBuf::as_path).collect();
+        let (mut failures, mut count) = (vec![], 0);
+        for name in names
+            .iter()
+            .filter(|name| !git::is_excluded(name, &excludes))
+        {
+            let path = match Path::new(name).strip_prefix(registered) {
+                Ok(relative) if relative.as_os_str().is_empty() => local.to_owned(),
+                Ok(relative) => local.join(relative),
+                Err(_) => continue,
+            };
+
+            count += 1;
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| test(&path)));
+            if let Err(payload) = result {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|st| st.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "<non-string panic payload>".to_string());
+                failures.push((name, message));
+            }
+        }
+
+        if count == 0 {
+            panic!("No cases in `{}`", registered.display());
+        }
+
+        if !failures.is_empty() {
+            let mut report = format!("{} of {} cases failed:\n", failures.len(), count);
+            for (name, message) in &failures {
+                report.push_str(&format!("  {}: {}\n", name, message));
+            }
+
+            panic!("{}", report);
+        }
+    }
+
+    /// The commit that the data is taken from.
+    ///
+    /// This is `HEAD` in a local repository, and the commit recorded in a crate archive. Note that
+    /// local data may differ from its commit, see [`FsData::object_id()`]. Returns `None` for data
+    /// without VCS information, such as an unpacked source download.
+    pub fn commit(&self) -> Option<&str> {
+        let commit = self.commit.get_or_init(|| match &self.tree {
+            Tree::Local { git, dir, .. } => Some(dir.head(git)),
+            Tree::Vcs { commit_id, .. } | Tree::Materialized { commit_id, .. } => {
+                Some(commit_id.clone())
+            }
+            Tree::Files => None,
+        });
+
+        commit.as_ref().map(|commit| commit.as_str())
+    }
+
+    /// The git object id of a registered file or tree, at the commit of the data.
+    ///
+    /// Use this as a stable key for caching results derived from test data, and embed it or
+    /// [`FsData::commit()`] in outputs to record their provenance. Returns `None` if the path is
+    /// not in the commit, or if the object ids are not available. That is the case for data
+    /// without VCS information, a tree materialized ahead of time, and files taken from a crate
+    /// archive without any pack objects.
+    ///
+    /// The id is determined once, on the first call for a path. Later changes to the local data
+    /// are not reflected in it or in [`ObjectId::is_dirty()`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut vcs = xtest_data::setup!();
+    /// let datazip = vcs.add("tests/data.zip");
+    /// let testdata = vcs.build();
+    ///
+    /// if let Some(id) = testdata.object_id(&datazip) {
+    ///     // Local changes are not part of the id, do not cache them.
+    ///     if !id.is_dirty() {
+    ///         println!("cache key {}", id);
+    ///     }
+    /// }
+    /// ```
+    pub fn object_id(&self, file: &Files) -> Option<ObjectId> {
+        self.object_ids[file.key]
+            .get_or_init(|| self.find_object_id(file))
+            .clone()
+    }
+
+    fn find_object_id(&self, file: &Files) -> Option<ObjectId> {
+        let registered = self.vcs_paths[file.key].as_path();
+        fn in_submodule<'lt>(
+            submodules: &'lt [git::Submodule],
+            path: &'lt Path,
+        ) -> Option<(&'lt git::Submodule, &'lt Path)> {
+            // A path containing a submodule is a tree of the superproject, with the gitlink.
+            submodules
+                .iter()
+                .find_map(|sub| Some((sub, path.strip_prefix(&sub.path).ok()?)))
+        }
+
+        match &self.tree {
+            Tree::Local {
+                git,
+                dir,
+                submodules,
+            } => {
+                let spec = |path| std::iter::once(git::PathSpec::Path(path));
+                match in_submodule(submodules, registered) {
+                    Some((sub, inner)) => {
+                        let subdir = dir.submodule(git, sub);
+                        let id = subdir.object_id(git, sub.commit.as_str(), inner)?;
+                        let dirty = subdir.head(git) != sub.commit
+                            || !subdir.tracked(git, &mut spec(inner)).is_empty();
+                        Some(ObjectId { id, dirty })
+                    }
+                    None => {
+                        let id = dir.object_id(git, "HEAD", registered)?;
+                        let dirty = !dir.tracked(git, &mut spec(registered)).is_empty();
+                        Some(ObjectId { id, dirty })
+                    }
+                }
+            }
+            Tree::Vcs {
+                git,
+                repo,
+                commit_id,
+                submodules,
+            } => {
+                let id = match in_submodule(submodules, registered) {
+                    Some((sub, inner)) => repo.object_id(git, &sub.commit, inner)?,
+                    None => repo.object_id(git, commit_id, registered)?,
+                };
+                Some(ObjectId { id, dirty: false })
+            }
+            Tree::Materialized { .. } | Tree::Files => None,
+        }
+    }
+
+    /// Compare the output of a test with a registered golden file.
+    ///
+    /// To update the golden files, run the tests in your repository with `XTEST_DATA_BLESS=1`, or
+    /// build the setup with [`SetupBuilder::bless()`]. Each mismatching file is then overwritten
+    /// with the actual output in the working tree, and reported, so that you can review and commit
+    /// the changes like any other test data. This is refused for data that was not used from a
+    /// local repository, e.g. in a crate archive, since such a change would never reach the data
+    /// of the crate.
+    ///
+    /// ## Panics
+    ///
+    /// This panics with a summary of the differences if the output does not match, unless the file
+    /// was blessed. It also panics if the registered path is not a file.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut vcs = xtest_data::setup!();
+    /// let expected = vcs.add("tests/cases/a.txt");
+    /// let testdata = vcs.build();
+    ///
+    /// let output = std::fs::read(testdata.path(&expected)).unwrap();
+    /// testdata.assert_matches(&expected, &output);
+    /// ```
+    #[track_caller]
+    pub fn assert_matches(&self, file: &Files, actual: &[u8]) {
+        let registered = &self.registered[file.key];
+        let path = self.path(file);
+
+        let expected = match fs::read(path) {
+            Ok(expected) => Some(expected),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => panic!("Can not read golden file {}: {}", registered.display(), err),
+        };
+
+        if expected.as_deref() == Some(actual) {
+            return;
+        }
+
+        let bless = self.bless;
+        let summary = match &expected {
+            Some(expected) => diff_summary(expected, actual),
+            None => format!(
+                "the file does not exist, the output has {} bytes",
+                actual.len()
+            ),
+        };
+
+        if bless {
+            if let Tree::Local { .. } = self.tree {
+                write_blessed(path, actual).unwrap_or_else(|err| {
+                    panic!(
+                        "Can not bless golden file {}: {}",
+                        registered.display(),
+                        err
+                    )
+                });
+                eprintln!(
+                    "xtest-data: blessed `{}`: {}",
+                    registered.display(),
+                    summary
+                );
+                return;
+            }
+
+            panic!(
+                "Output differs from golden file `{}`: {}\n\
+                Refusing to bless data that is not used from a local repository. Run the test in \
+                the repository and commit the golden file instead.",
+                registered.display(),
+                summary
+            );
+        }
+
+        panic!(
+            "Output differs from golden file `{}`: {}\n\
+            Run the test with XTEST_DATA_BLESS=1 in the repository to update it.",
+            registered.display(),
+            summary
+        );
+    }
+
+    /// Retrieve the rewritten path by the path it was registered with.
+    ///
+    /// The argument is interpreted relative to the manifest, in the same way as in
+    /// [`Setup::add()`]. A path that refers to a file within a registered tree is rewritten as
+    /// well, by the first such tree in the order of registration. Paths given to
+    /// [`Setup::rewrite()`] come after all others. Returns `None` if the path is not covered by
+    /// any registered file or tree.
+    pub fn resolve(&self, path: impl AsRef<Path>) -> Option<PathBuf> {
+        let path = normalize_path(path.as_ref())?;
+        self.resolved().find_map(|(registered, local)| {
+            let relative = path.strip_prefix(registered).ok()?;
+            Some(if relative.as_os_str().is_empty() {
+                local.clone()
+            } else {
+                local.join(relative)
+            })
+        })
+    }
+
+    /// The registered paths with their location, then those of `Setup::rewrite`.
+    fn resolved(&self) -> impl Iterator<Item = (&PathBuf, &PathBuf)> {
+        let rewritten = self
+            .rewritten
+            .iter()
+            .map(|(registered, local)| (registered, local));
+        self.registered.iter().zip(&self.map).chain(rewritten)
+    }
+}
+
+impl ObjectId {
+    /// The hex id of the object.
+    pub fn as_str(&self) -> &str {
+        &self.id
+    }
+
+    /// Does the local data differ from this object?
+    ///
+    /// Only data in a local repository can be dirty, that is modified, staged or deleted relative
+    /// to `HEAD`. The object id then does not describe the content that the test uses.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+}
+
+impl core::fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(&self.id)
+    }
+}
+
+impl Drop for FsData {
+    fn drop(&mut self) {
+        let checkout = match self.checkout.take() {
+            Some(checkout) => checkout,
+            None => return,
+        };
+
+        if std::thread::panicking() {
+            checkout.keep_for_failure();
+            return;
+        }
+
+        let keep = env::var_os("CARGO_XTEST_DATA_KEEP_TREE").is_some_and(|keep| keep == "always");
+        if keep {
+            return;
+        }
+
+        if checkout.rewritten {
+            record_panics();
+            // If the thread is already exiting, the checkout is kept.
+            let _ = OUTLIVING.try_with(|outliving| outliving.checkouts.borrow_mut().push(checkout));
+            return;
+        }
+
+        checkout.remove();
+    }
+}
+
+impl Drop for Outliving {
+    fn drop(&mut self) {
+        let panicked = PANICKED.with(Cell::get);
+        for checkout in self.checkouts.get_mut().drain(..) {
+            if panicked {
+                checkout.keep_for_failure();
+            } else {
+                checkout.remove();
+            }
+        }
+    }
+}
+
+/// Chain a panic hook that records the panics of each thread, see `PANICKED`.
+fn record_panics() {
+    static HOOK: std::sync::Once = std::sync::Once::new();
+    HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            PANICKED.with(|panicked| panicked.set(true));
+            previous(info)
+        }));
+    });
+}
+
+impl Checkout {
+    fn remove(self) {
+        // Removal is best effort, the directory is in a temporary location anyways.
+        if let Err(err) = fs::remove_dir_all(&self.dir) {
+            log::debug(format_args!(
+                "could not remove {}: {}",
+                self.dir.display(),
+                err
+            ));
+        }
+    }
+
+    /// Describe how to find and reproduce the data of a failed test.
+    ///
+    /// Called while unwinding, so this must not panic itself.
+    fn keep_for_failure(&self) {
+        let mut files = vec![];
+        for path in &self.paths {
+            if let Ok(listed) = tree::list_files(&self.dir.join(path), path) {
+                files.extend(listed);
+            }
+        }
+
+        let mapping = tree::write_mapping(&self.dir, &self.commit_id, files);
+
+        eprintln!("xtest-data: The test failed, its data is kept for inspection.");
+        eprintln!("  tree:   {}", self.dir.display());
+        eprintln!("  commit: {}", self.commit_id);
+        let specs: Vec<_> = self
+            .paths
+            .iter()
+            .map(|path| git::PathSpec::Path(path).to_string())
+            .collect();
+        eprintln!("  paths:  {}", specs.join(" "));
+
+        let test = match &self.test {
+            Some(name) => format!(" -- {} --exact", log::shell_word(name.as_ref())),
+            None => String::new(),
+        };
+        let cd = format!("cd {} &&", log::shell_word(self.manifest.as_os_str()));
+        let environment: String = self
+            .environment
+            .iter()
+            .map(|(key, value)| format!(" {}={}", key, log::shell_word(value)))
+            .collect();
+
+        eprintln!("Reproduce the test of the crate archive with:");
+        eprintln!("  {}{} cargo test{}", cd, environment, test);
+        if self.complete && mapping.is_ok() {
+            eprintln!("Or rerun with this data, without git:");
+            let tree = log::shell_word(self.dir.as_os_str());
+            eprintln!("  {} CARGO_XTEST_DATA_TREE={} cargo test{}", cd, tree, test);
+        }
+    }
 }
 
 impl Managed {
     pub fn as_path(&self) -> &Path {
         match self {
-            Managed::Files(path) => path,
+            Managed::Files(path) | Managed::Excluding { path, .. } => path,
         }
     }
 
     fn as_path_spec(&self) -> git::PathSpec<'_> {
+        git::PathSpec::Path(self.as_path())
+    }
+
+    fn excludes(&self) -> &[PathBuf] {
         match self {
-            Managed::Files(path) => git::PathSpec::Path(path),
+            Managed::Files(_) => &[],
+            Managed::Excluding { excludes, .. } => excludes,
+        }
+    }
+}
+
+/// The pathspecs for `paths`, without anything under `excludes`.
+fn pathspecs<'lt>(
+    paths: &'lt [&'lt Path],
+    excludes: &'lt [&'lt Path],
+) -> impl Iterator<Item = git::PathSpec<'lt>> {
+    let paths = paths.iter().map(|path| git::PathSpec::Path(path));
+    paths.chain(excludes.iter().map(|path| git::PathSpec::Exclude(path)))
+}
+
+/// Remove `.` and resolve `..` components of a relative path, without accessing the file system.
+///
+/// A path that starts outside of its base keeps the leading `..` components. Returns `None` for
+/// absolute paths.
+fn normalize_path(path: &Path) -> Option<PathBuf> {
+    use std::path::Component;
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                _ => normalized.push(".."),
+            },
+            Component::RootDir | Component::Prefix(_) => return None,
         }
     }
+
+    Some(normalized)
+}
+
+/// The root of the repository, given a directory and its path in the repository.
+fn repository_root(dir: &Path, prefix: Option<&Path>) -> PathBuf {
+    let depth = prefix.map_or(0, |prefix| prefix.components().count());
+    dir.ancestors()
+        .nth(depth)
+        .unwrap_or_else(|| inconclusive(&mut format!("{} is not in the repository", dir.display())))
+        .to_owned()
+}
+
+fn set_root(path: &Path, dir: &mut PathBuf) -> PathBuf {
+    *dir = path.join(&*dir);
+    dir.clone()
+}
+
+/// Describe where two versions of a golden file differ.
+fn diff_summary(expected: &[u8], actual: &[u8]) -> String {
+    let (expected_text, actual_text) =
+        match (std::str::from_utf8(expected), std::str::from_utf8(actual)) {
+            (Ok(expected), Ok(actual)) => (expected, actual),
+            _ => {
+                let first = expected
+                    .iter()
+                    .zip(actual)
+                    .position(|(a, b)| a != b)
+                    .unwrap_or_else(|| expected.len().min(actual.len()));
+                return format!(
+                    "expected {} bytes, got {} bytes, first difference at byte {}",
+                    expected.len(),
+                    actual.len(),
+                    first
+                );
+            }
+        };
+
+    let (expected_lines, actual_lines): (Vec<_>, Vec<_>) = (
+        expected_text.lines().collect(),
+        actual_text.lines().collect(),
+    );
+    let length = expected_lines.len().max(actual_lines.len());
+    let differing: Vec<usize> = (0..length)
+        .filter(|&idx| expected_lines.get(idx) != actual_lines.get(idx))
+        .collect();
+
+    let first = match differing.first() {
+        Some(&first) => first,
+        // Only line endings, or a final newline, differ.
+        None => {
+            return format!(
+                "expected {} bytes, got {} bytes, the lines only differ in their endings",
+                expected.len(),
+                actual.len()
+            )
+        }
+    };
+
+    let show = |line: Option<&&str>| {
+        line.map_or_else(|| "<none>".to_owned(), |line| format!("{:?}", line))
+    };
+    format!(
+        "{} of {} line(s) differ, first at line {}\n  expected: {}\n  actual:   {}",
+        differing.len(),
+        length,
+        first + 1,
+        show(expected_lines.get(first)),
+        show(actual_lines.get(first)),
+    )
 }
 
-fn set_root(path: &Path, dir: &mut PathBuf) {
-    *dir = path.join(&*dir)
+/// Overwrite a golden file in the working tree, even if the data was made read-only.
+fn write_blessed(path: &Path, actual: &[u8]) -> io::Result<()> {
+    let permissions = match fs::metadata(path) {
+        Ok(meta) => Some(meta.permissions()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+
+    if let Some(permissions) = &permissions {
+        let mut writable = permissions.clone();
+        set_writable(&mut writable);
+        fs::set_permissions(path, writable)?;
+    }
+
+    fs::write(path, actual)?;
+
+    match permissions {
+        Some(permissions) => fs::set_permissions(path, permissions),
+        None => Ok(()),
+    }
+}
+
+/// A fingerprint of the index and of every file and directory under `paths`, with the newest
+/// modification time among them.
+///
+/// Like the stat data that git compares with its index, it covers the name, type, size and
+/// modification time of each file, and on unix the inode, mode and change time. Any change to the
+/// files, including new and removed ones, changes it. A change of what git knows about them, such
+/// as staging, changes the index. Returns `None` if the index can not be read.
+fn fingerprint(root: &Path, index: &Path, paths: &[&Path]) -> Option<(String, SystemTime)> {
+    use sha2::Digest;
+
+    fn stat(digest: &mut sha2::Sha256, newest: &mut SystemTime, meta: &fs::Metadata) {
+        let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        *newest = (*newest).max(modified);
+        let nanos = |time: SystemTime| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |since| since.as_nanos())
+        };
+
+        digest.update(format!(
+            "{:?} {} {}",
+            meta.file_type(),
+            meta.len(),
+            nanos(modified)
+        ));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            digest.update(format!(
+                " {} {} {} {} {}",
+                meta.dev(),
+                meta.ino(),
+                meta.mode(),
+                meta.ctime(),
+                meta.ctime_nsec()
+            ));
+        }
+        digest.update("\n");
+    }
+
+    fn visit(digest: &mut sha2::Sha256, newest: &mut SystemTime, root: &Path, path: &Path) {
+        digest.update(path.to_string_lossy().as_bytes());
+        digest.update("\0");
+
+        let meta = match fs::symlink_metadata(root.join(path)) {
+            Ok(meta) => meta,
+            Err(err) => return digest.update(format!("{:?}\n", err.kind())),
+        };
+
+        stat(digest, newest, &meta);
+        if !meta.is_dir() {
+            return;
+        }
+
+        let mut names: Vec<_> = match fs::read_dir(root.join(path)) {
+            Ok(entries) => entries
+                .filter_map(|entry| Some(entry.ok()?.file_name()))
+                .collect(),
+            Err(err) => return digest.update(format!("{:?}\n", err.kind())),
+        };
+
+        names.sort();
+        for name in names {
+            visit(digest, newest, root, &path.join(name));
+        }
+    }
+
+    let mut digest = sha2::Sha256::new();
+    let mut newest = SystemTime::UNIX_EPOCH;
+    digest.update(root.to_string_lossy().as_bytes());
+    digest.update("\0");
+    stat(&mut digest, &mut newest, &fs::metadata(index).ok()?);
+    for path in paths {
+        visit(&mut digest, &mut newest, root, path);
+    }
+
+    Some((format!("{:x}", digest.finalize()), newest))
+}
+
+/// Could a file have changed after a check that started at `start`, without a new fingerprint?
+///
+/// As git does for its index, we distrust modification times that are too close to the check.
+/// A file system may only store them in seconds, then a change in the same second is invisible.
+fn racy(newest: SystemTime, start: SystemTime) -> bool {
+    newest + std::time::Duration::from_secs(1) >= start
+}
+
+/// Write a marker, replacing an earlier one at once.
+fn write_marker(marker: &Path, fingerprint: &str) -> io::Result<()> {
+    let dir = marker.parent().ok_or(io::ErrorKind::NotFound)?;
+    fs::create_dir_all(dir)?;
+    let partial = marker.with_extension(format!("{}", std::process::id()));
+    fs::write(&partial, fingerprint)?;
+    fs::rename(partial, marker)
+}
+
+/// Remove write permissions from all files in a tree.
+fn set_read_only(path: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.is_dir() {
+        for entry in fs::read_dir(path)? {
+            set_read_only(&entry?.path())?;
+        }
+    } else if meta.is_file() {
+        let mut permissions = meta.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(path, permissions)?;
+    }
+
+    Ok(())
+}
+
+/// Copy a file or tree to a new location, ensuring that the copy is writable.
+fn copy_writable(from: &Path, to: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    if meta.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_writable(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else if meta.file_type().is_symlink() {
+        copy_symlink(from, to)?;
+    } else {
+        fs::copy(from, to)?;
+        let mut permissions = fs::metadata(to)?.permissions();
+        set_writable(&mut permissions);
+        fs::set_permissions(to, permissions)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    // No portable way to create the link, copy the contents instead.
+    fs::copy(from, to).map(drop)
+}
+
+#[cfg(unix)]
+fn set_writable(permissions: &mut fs::Permissions) {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.set_mode(permissions.mode() | 0o200);
+}
+
+#[cfg(not(unix))]
+fn set_writable(permissions: &mut fs::Permissions) {
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
 }
 
 // We do not use tempdir. This should already be done by our environment (e.g. cargo).
@@ -534,9 +2703,52 @@ fn unique_dir(base: &Path, prefix: &str) -> Result<PathBuf, std::io::Error> {
     }
 }
 
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Run a build, returning the failures of `fail` and `inconclusive` instead of panicking.
+///
+/// Other panics, such as those of invalid arguments, are not caught.
+fn fallible<T>(build: impl FnOnce() -> T) -> Result<T, Error> {
+    let outer = FALLIBLE.with(|fallible| fallible.replace(true));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(build));
+    FALLIBLE.with(|fallible| fallible.set(outer));
+
+    result.map_err(|payload| match payload.downcast::<Inconclusive>() {
+        Ok(inconclusive) => Error {
+            message: inconclusive.0,
+        },
+        Err(payload) => std::panic::resume_unwind(payload),
+    })
+}
+
+/// Panic with a message, or return it as the error of a fallible build.
+#[cold]
+#[track_caller]
+fn fail(message: String) -> ! {
+    if FALLIBLE.with(Cell::get) {
+        // Does not run the panic hook, there is no test failure to report.
+        std::panic::resume_unwind(Box::new(Inconclusive(message)));
+    }
+    panic!("{}", message);
+}
+
 #[cold]
 #[track_caller]
 fn inconclusive(err: &mut dyn std::fmt::Display) -> ! {
+    log::info(format_args!(
+        "failed at {}: {}",
+        std::panic::Location::caller(),
+        err
+    ));
+    if FALLIBLE.with(Cell::get) {
+        fail(err.to_string());
+    }
     eprintln!("xtest-data failed to setup.");
     eprintln!("Information: {}", err);
     panic!();
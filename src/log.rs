@@ -0,0 +1,226 @@
+//! Diagnostics, configured with `CARGO_XTEST_DATA_LOG`.
+//!
+//! The value is either a level (`off`, `info`, `debug`) for messages on stderr, or the path of a
+//! file to which all messages are appended. At `info` we describe each `Setup::build`, at `debug`
+//! we additionally record every git invocation.
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::ops::{Deref, DerefMut};
+use std::process::{Child, Command, ExitStatus, Output};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Level {
+    Off,
+    Info,
+    Debug,
+}
+
+struct Logger {
+    level: Level,
+    /// Where to write, or stderr if there is no file.
+    file: Option<Mutex<File>>,
+}
+
+/// A running process whose completion will be logged.
+pub(crate) struct Running {
+    child: Child,
+    command: String,
+    start: Instant,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+static GIT_COUNT: AtomicUsize = AtomicUsize::new(0);
+static GIT_NANOS: AtomicU64 = AtomicU64::new(0);
+
+fn logger() -> &'static Logger {
+    LOGGER.get_or_init(|| {
+        let value = match std::env::var_os("CARGO_XTEST_DATA_LOG") {
+            None => return Logger::OFF,
+            Some(value) => value,
+        };
+
+        let level = match value.to_str() {
+            Some("" | "0" | "off") => Some(Level::Off),
+            Some("info") => Some(Level::Info),
+            Some("debug") => Some(Level::Debug),
+            _ => None,
+        };
+
+        if let Some(level) = level {
+            return Logger { level, file: None };
+        }
+
+        match OpenOptions::new().create(true).append(true).open(&value) {
+            Ok(file) => Logger {
+                level: Level::Debug,
+                file: Some(Mutex::new(file)),
+            },
+            Err(err) => {
+                eprintln!(
+                    "xtest-data: Can not open log file {}: {}",
+                    std::path::Path::new(&value).display(),
+                    err
+                );
+                Logger::OFF
+            }
+        }
+    })
+}
+
+impl Logger {
+    const OFF: Logger = Logger {
+        level: Level::Off,
+        file: None,
+    };
+
+    fn write(&self, args: fmt::Arguments<'_>) {
+        match &self.file {
+            None => eprintln!("xtest-data: {}", args),
+            Some(file) => {
+                let mut file = file.lock().unwrap_or_else(|poison| poison.into_inner());
+                // Diagnostics must never fail a test on their own.
+                let _ = writeln!(file, "xtest-data: {}", args);
+            }
+        }
+    }
+}
+
+pub(crate) fn enabled(level: Level) -> bool {
+    level <= logger().level
+}
+
+pub(crate) fn info(args: fmt::Arguments<'_>) {
+    if enabled(Level::Info) {
+        logger().write(args);
+    }
+}
+
+pub(crate) fn debug(args: fmt::Arguments<'_>) {
+    if enabled(Level::Debug) {
+        logger().write(args);
+    }
+}
+
+/// The number of git invocations and the total time spent in them, for this process.
+pub(crate) fn git_stats() -> (usize, Duration) {
+    let count = GIT_COUNT.load(Ordering::Relaxed);
+    let nanos = GIT_NANOS.load(Ordering::Relaxed);
+    (count, Duration::from_nanos(nanos))
+}
+
+/// Run a command to completion, like `Command::output`.
+pub(crate) fn output(cmd: &mut Command) -> io::Result<Output> {
+    let start = Instant::now();
+    let output = cmd.output();
+    finished(&describe(cmd), start, output.as_ref());
+    output
+}
+
+/// Run a command to completion, like `Command::status`.
+///
+/// Other than `Command::status` this does not inherit standard streams that were not configured,
+/// it captures them like `Command::output` and closes stdin. All callers run git with the streams
+/// of `exec`, which discards stdout and pipes stderr into the debug log.
+pub(crate) fn status(cmd: &mut Command) -> io::Result<ExitStatus> {
+    output(cmd).map(|output| output.status)
+}
+
+/// Start a command, like `Command::spawn`.
+pub(crate) fn spawn(cmd: &mut Command) -> io::Result<Running> {
+    let command = describe(cmd);
+    let start = Instant::now();
+    match cmd.spawn() {
+        Ok(child) => Ok(Running {
+            child,
+            command,
+            start,
+        }),
+        Err(err) => {
+            finished(&command, start, Err(&err));
+            Err(err)
+        }
+    }
+}
+
+impl Running {
+    pub(crate) fn wait_with_output(self) -> io::Result<Output> {
+        let output = self.child.wait_with_output();
+        finished(&self.command, self.start, output.as_ref());
+        output
+    }
+}
+
+impl Deref for Running {
+    type Target = Child;
+    fn deref(&self) -> &Child {
+        &self.child
+    }
+}
+
+impl DerefMut for Running {
+    fn deref_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+}
+
+fn describe(cmd: &Command) -> String {
+    let mut words = vec![shell_word(cmd.get_program())];
+    words.extend(cmd.get_args().map(shell_word));
+    if let Some(dir) = cmd.get_current_dir() {
+        words.push(format!("(in {})", dir.display()));
+    }
+    words.join(" ")
+}
+
+/// Quote a word for a POSIX shell, unless it is plain.
+///
+/// Braces are quoted, `a{b,c}` would be expanded by some shells.
+pub(crate) fn shell_word(word: &OsStr) -> String {
+    let word = word.to_string_lossy();
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "-_=./:,+@%^".contains(ch))
+    {
+        word.into_owned()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+fn finished(command: &str, start: Instant, result: Result<&Output, &io::Error>) {
+    let elapsed = start.elapsed();
+    GIT_COUNT.fetch_add(1, Ordering::Relaxed);
+    GIT_NANOS.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+
+    if !enabled(Level::Debug) {
+        return;
+    }
+
+    match result {
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stderr = stderr.trim_end();
+            if stderr.is_empty() {
+                debug(format_args!(
+                    "{} ({:.2?}, {})",
+                    command, elapsed, output.status
+                ));
+            } else {
+                debug(format_args!(
+                    "{} ({:.2?}, {})\n  {}",
+                    command,
+                    elapsed,
+                    output.status,
+                    stderr.replace('\n', "\n  ")
+                ));
+            }
+        }
+        Err(err) => debug(format_args!("{} ({:.2?}, {})", command, elapsed, err)),
+    }
+}
@@ -0,0 +1,151 @@
+//! The `[package.metadata.xtest-data]` table of the manifest, as configured by the author.
+//!
+//! This is not a TOML parser. It reads the normalized manifest that cargo writes into a crate
+//! archive: table headers, and keys with a string value on a single line. Everything else is
+//! skipped, so a manifest in the repository is only understood if written in the same way.
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub(crate) struct Metadata {
+    /// The string values of `[package]`, available in the templates.
+    package: HashMap<String, String>,
+    /// The URL template of the published pack artifact.
+    pack_artifact: Option<String>,
+    /// The template of the pack objects directory, relative to the manifest.
+    pack_objects: Option<String>,
+}
+
+impl Metadata {
+    /// Read the `Cargo.toml` of a manifest directory, if there is any.
+    pub fn read(manifest: &Path) -> Option<Self> {
+        let toml = fs::read_to_string(manifest.join("Cargo.toml")).ok()?;
+        Some(Self::parse(&toml))
+    }
+
+    fn parse(toml: &str) -> Self {
+        let mut meta = Metadata::default();
+        let mut table = String::new();
+
+        for line in toml.lines() {
+            let line = line.trim();
+            if line.starts_with("[[") {
+                // An array of tables, none of which we are interested in.
+                table = String::from("[[");
+            } else if let Some(header) = line.strip_prefix('[') {
+                let header = header.split(']').next().unwrap_or_default();
+                table = normalize_key(header);
+            } else if let Some((key, value)) = line.split_once('=') {
+                let value = match parse_string(value.trim()) {
+                    Some(value) => value,
+                    None => continue,
+                };
+
+                let key = normalize_key(key);
+                let key = if table.is_empty() {
+                    key
+                } else {
+                    format!("{}.{}", table, key)
+                };
+
+                match key.as_str() {
+                    "package.metadata.xtest-data.pack-artifact" => meta.pack_artifact = Some(value),
+                    "package.metadata.xtest-data.pack-objects" => meta.pack_objects = Some(value),
+                    _ => {
+                        if let Some(name) = key.strip_prefix("package.") {
+                            if !name.contains('.') {
+                                meta.package.insert(name.to_owned(), value);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        meta
+    }
+
+    /// The URL of the pack artifact, with `{name}`, `{version}` and other package keys replaced.
+    pub fn pack_artifact(&self) -> Option<String> {
+        self.pack_artifact.as_deref().map(|url| self.render(url))
+    }
+
+    /// The directory of pack objects, relative to the manifest.
+    pub fn pack_objects(&self) -> Option<String> {
+        self.pack_objects.as_deref().map(|dir| self.render(dir))
+    }
+
+    fn render(&self, template: &str) -> String {
+        let mut rendered = String::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let value = after
+                .find('}')
+                .and_then(|end| Some((self.package.get(after[..end].trim())?, end)));
+
+            match value {
+                Some((value, end)) => {
+                    rendered.push_str(value);
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    rendered.push('{');
+                    rest = after;
+                }
+            }
+        }
+
+        rendered.push_str(rest);
+        rendered
+    }
+}
+
+/// A dotted key or table name, without quotes and whitespace around its parts.
+fn normalize_key(key: &str) -> String {
+    key.split('.')
+        .map(|part| part.trim().trim_matches(|ch| ch == '"' || ch == '\''))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// A basic or literal string on one line, with an optional comment after it.
+fn parse_string(value: &str) -> Option<String> {
+    if let Some(literal) = value.strip_prefix('\'') {
+        if literal.starts_with("''") {
+            return None;
+        }
+
+        return literal.split_once('\'').map(|(value, _)| value.to_owned());
+    }
+
+    let basic = value.strip_prefix('"')?;
+    if basic.starts_with("\"\"") {
+        return None;
+    }
+
+    let mut value = String::new();
+    let mut chars = basic.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    value.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                escaped @ ('\\' | '"') => value.push(escaped),
+                _ => return None,
+            },
+            ch => value.push(ch),
+        }
+    }
+
+    // Not terminated on this line.
+    None
+}
@@ -0,0 +1,130 @@
+//! A data tree that was materialized ahead of time, used without any access to `git`.
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tinyjson::JsonValue;
+
+use crate::{git, inconclusive, GetKey};
+
+/// The name of the file describing a materialized tree, in its root directory.
+pub(crate) const MAPPING: &str = "xtest-data-tree.json";
+
+/// A checkout of the data on the host, prepared by `cargo xtest-data materialize`.
+#[derive(Debug)]
+pub(crate) struct Materialized {
+    /// The root of the tree, corresponding to the root of the repository.
+    pub root: PathBuf,
+    /// The commit from which the files were checked out.
+    pub commit_id: git::CommitId,
+    /// All files in the tree, relative to the root, in tree order.
+    pub files: Vec<String>,
+}
+
+impl Materialized {
+    /// Read the mapping of a tree.
+    /// Aborts if this isn't possible (see error handling policy).
+    pub fn read(root: PathBuf) -> Self {
+        let data = fs::read_to_string(root.join(MAPPING)).unwrap_or_else(|err| {
+            inconclusive(&mut format!(
+                "Expected a materialized data tree at {}: {}",
+                root.display(),
+                err
+            ))
+        });
+
+        let mapping: JsonValue = data
+            .parse()
+            .unwrap_or_else(|mut err| inconclusive(&mut err));
+
+        let commit_id = mapping
+            .get_key("commit")
+            .and_then(|id| id.get::<String>())
+            .map(|id| git::CommitId::from(&**id))
+            .unwrap_or_else(|| inconclusive(&mut "Data tree mapping has no commit ID"));
+
+        let files = mapping
+            .get_key("files")
+            .and_then(|files| files.get::<Vec<JsonValue>>())
+            .unwrap_or_else(|| inconclusive(&mut "Data tree mapping has no list of files"))
+            .iter()
+            .map(|file| {
+                file.get::<String>()
+                    .cloned()
+                    .unwrap_or_else(|| inconclusive(&mut "Data tree file is not a string"))
+            })
+            .collect();
+
+        Materialized {
+            root,
+            commit_id,
+            files,
+        }
+    }
+}
+
+/// Write the mapping of a tree checked out from `commit_id`, in the format that `read` expects.
+pub(crate) fn write_mapping(
+    root: &Path,
+    commit_id: &git::CommitId,
+    files: Vec<String>,
+) -> io::Result<()> {
+    let mapping = JsonValue::from({
+        let mut map = HashMap::new();
+        map.insert("commit".to_string(), JsonValue::from(commit_id.to_string()));
+        map.insert(
+            "files".to_string(),
+            JsonValue::from(files.into_iter().map(JsonValue::from).collect::<Vec<_>>()),
+        );
+        map
+    });
+
+    let mapping = mapping
+        .stringify()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    fs::write(root.join(MAPPING), mapping)
+}
+
+/// List the files of `path`, stored at `dir`, in the order of a git tree.
+///
+/// This is for data that we have no VCS information about. Git sorts tree entries by their name
+/// bytes, where directories compare as if their name ended with a slash. The names are relative to
+/// the repository root, i.e. start with `path`.
+pub(crate) fn list_files(dir: &Path, path: &Path) -> io::Result<Vec<String>> {
+    fn walk(dir: &Path, name: String, files: &mut Vec<String>) -> io::Result<()> {
+        let meta = fs::symlink_metadata(dir)?;
+        if !meta.is_dir() {
+            files.push(name);
+            return Ok(());
+        }
+
+        let mut entries = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let mut name = entry
+                .file_name()
+                .into_string()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Non-UTF-8 file name"))?;
+            if entry.file_type()?.is_dir() {
+                name.push('/');
+            }
+            entries.push(name);
+        }
+
+        entries.sort();
+        for entry in entries {
+            let entry = entry.trim_end_matches('/');
+            walk(&dir.join(entry), format!("{}/{}", name, entry), files)?;
+        }
+
+        Ok(())
+    }
+
+    let name = path
+        .to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Non-UTF-8 path"))?;
+    let mut files = vec![];
+    walk(dir, name.trim_end_matches('/').to_owned(), &mut files)?;
+    Ok(files)
+}
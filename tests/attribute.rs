@@ -0,0 +1,25 @@
+use std::path::Path;
+
+#[xtest_data::test(data = "tests/data.zip")]
+fn attribute_integration(datazip: &Path) {
+    assert!(datazip.exists(), "{}", datazip.display());
+}
+
+#[xtest_data::test(data = "tests/data.zip", data = "Readme.md")]
+fn attribute_multiple(datazip: &Path, readme: &Path) -> Result<(), String> {
+    assert!(readme.exists(), "{}", readme.display());
+    assert!(datazip.exists(), "{}", datazip.display());
+    Ok(())
+}
+
+#[xtest_data::test]
+#[should_panic]
+fn attribute_without_data() {
+    panic!("Attributes are kept");
+}
+
+#[xtest_data::test(data = "tests/data.zip")]
+#[::core::prelude::v1::test]
+fn attribute_with_test_path(datazip: &Path) {
+    assert!(datazip.exists(), "{}", datazip.display());
+}
@@ -17,3 +17,153 @@ fn simple_integration() {
     // 'Magically' changed.
     assert!(path.exists(), "{}", path.display());
 }
+
+fn shared_data() -> &'static xtest_data::FsData {
+    xtest_data::shared!("tests/data.zip")
+}
+
+#[test]
+fn shared_integration() {
+    let path = shared_data().resolve("tests/data.zip").unwrap();
+    assert!(path.exists(), "{}", path.display());
+    // Every use refers to the same, single setup.
+    assert!(std::ptr::eq(shared_data(), shared_data()));
+}
+
+#[test]
+fn scratch_copy_is_private() {
+    let mut vcs = xtest_data::setup!();
+    let datazip = vcs.add("tests/data.zip");
+    let testdata = vcs.build();
+
+    let original = testdata.path(&datazip);
+    let scratch = testdata.scratch_copy(&datazip);
+    assert_ne!(scratch, original);
+    assert!(scratch.ends_with("tests/data.zip"), "{}", scratch.display());
+    std::fs::write(&scratch, b"modified").unwrap();
+
+    assert_ne!(std::fs::read(original).unwrap(), b"modified");
+}
+
+#[test]
+fn paths_from_env() {
+    // In the child process, the variable of the parent is the only source of paths.
+    if let Ok(expected) = std::env::var("XTEST_DATA_EXPECTED_PATH") {
+        let inherited = xtest_data::FsData::from_env().unwrap();
+        assert_registration_order(&inherited.to_env().1);
+        assert!(inherited.resolve("tests/cases/a.txt").is_some());
+        assert_eq!(inherited.resolve("tests/data.zip"), Some(expected.into()));
+        return;
+    }
+
+    let mut datazip = PathBuf::from("tests/data.zip");
+    let mut vcs = xtest_data::setup!().rewrite([&mut datazip]);
+    vcs.add("tests/cases/nested");
+    vcs.add("tests/cases");
+    let testdata = vcs.build();
+
+    let (key, value) = testdata.to_env();
+    assert_registration_order(&value);
+
+    let child = |paths: &std::ffi::OsStr| {
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["paths_from_env", "--exact", "--quiet"])
+            .env(key, paths)
+            .env("XTEST_DATA_EXPECTED_PATH", &datazip)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}", stdout);
+    };
+
+    child(value.as_ref());
+
+    let file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("xtest-data-paths.json");
+    testdata.write_json(&file);
+    child(file.as_os_str());
+}
+
+fn assert_registration_order(json: &str) {
+    let position = |path: &str| json.find(&format!("\"{}\"", path)).unwrap();
+    assert!(
+        position("tests/cases/nested") < position("tests/cases"),
+        "{}",
+        json
+    );
+    assert!(
+        position("tests/cases") < position("tests/data.zip"),
+        "{}",
+        json
+    );
+}
+
+#[test]
+fn cases_in_tree_order() {
+    let mut vcs = xtest_data::setup!();
+    let corpus = vcs.add("tests/cases");
+    let testdata = vcs.build();
+
+    let mut seen = vec![];
+    testdata.cases(&corpus, |path| {
+        seen.push(
+            path.strip_prefix(testdata.path(&corpus))
+                .unwrap()
+                .to_owned(),
+        );
+    });
+
+    let expected = ["a.txt", "b.txt", "nested/c.txt"];
+    assert_eq!(seen, expected.map(PathBuf::from));
+}
+
+#[test]
+fn cases_report_all_failures() {
+    let mut vcs = xtest_data::setup!();
+    let corpus = vcs.add("tests/cases");
+    let testdata = vcs.build();
+
+    let mut count = 0;
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        testdata.cases(&corpus, |path| {
+            count += 1;
+            assert!(path.ends_with("b.txt"), "not b");
+        })
+    }));
+
+    assert_eq!(count, 3);
+    let report = result.unwrap_err();
+    let report = report.downcast_ref::<String>().unwrap();
+    assert!(report.contains("2 of 3 cases failed"), "{}", report);
+    assert!(report.contains("tests/cases/a.txt: not b"), "{}", report);
+    assert!(
+        report.contains("tests/cases/nested/c.txt: not b"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn builder_integration() {
+    let mut setup = xtest_data::Setup::builder()
+        .package(env!("CARGO_PKG_NAME"))
+        .repository(env!("CARGO_PKG_REPOSITORY"))
+        .manifest_dir(env!("CARGO_MANIFEST_DIR"))
+        .tmpdir(env!("CARGO_TARGET_TMPDIR"))
+        .build();
+    let datazip = setup.add("tests/data.zip");
+    let testdata = setup.build();
+
+    let path = testdata.path(&datazip);
+    assert!(path.exists(), "{}", path.display());
+}
+
+#[test]
+#[should_panic]
+fn builder_explicit_vcs_info() {
+    let missing = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("no-such-vcs-info.json");
+    let _ = xtest_data::Setup::builder()
+        .repository(env!("CARGO_PKG_REPOSITORY"))
+        .manifest_dir(env!("CARGO_MANIFEST_DIR"))
+        .vcs_info(missing)
+        .build();
+}
@@ -0,0 +1,46 @@
+//! File types and modes of the data in `tests/modes`, which must survive every checkout.
+#![cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+fn executable(path: &Path) -> bool {
+    let meta = std::fs::symlink_metadata(path).unwrap();
+    meta.is_file() && meta.permissions().mode() & 0o111 != 0
+}
+
+fn link(path: &Path) -> String {
+    let target = std::fs::read_link(path).unwrap();
+    target.to_str().unwrap().to_owned()
+}
+
+#[test]
+fn modes_are_reproduced() {
+    let mut vcs = xtest_data::setup!();
+    let modes = vcs.add("tests/modes");
+    let testdata = vcs.build();
+    let root = testdata.path(&modes);
+
+    assert!(executable(&root.join("run.sh")));
+    assert!(!executable(&root.join("data/plain.txt")));
+
+    assert_eq!(link(&root.join("link-to-file")), "data/plain.txt");
+    assert_eq!(link(&root.join("link-to-dir")), "data");
+    assert_eq!(link(&root.join("data/link-up")), "../run.sh");
+
+    let plain = std::fs::read_to_string(root.join("link-to-dir/plain.txt")).unwrap();
+    assert_eq!(plain, "plain\n");
+    assert!(executable(
+        &root.join("link-to-dir/link-up").canonicalize().unwrap()
+    ));
+}
+
+#[test]
+fn modes_of_scratch_copy() {
+    let mut vcs = xtest_data::setup!();
+    let modes = vcs.add("tests/modes");
+    let testdata = vcs.build();
+    let root = testdata.scratch_copy(&modes);
+
+    assert!(executable(&root.join("run.sh")));
+    assert_eq!(link(&root.join("link-to-file")), "data/plain.txt");
+}
@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+
+#[test]
+fn path_macro_integration() {
+    let mut datazip = xtest_data::path!("tests/data.zip");
+    let mut cases = xtest_data::path!(r"tests/cases",);
+    assert_eq!(datazip, PathBuf::from("tests/data.zip"));
+
+    xtest_data::setup!()
+        .rewrite([&mut datazip, &mut cases])
+        .build();
+    assert!(datazip.exists(), "{}", datazip.display());
+    assert!(cases.join("a.txt").exists(), "{}", cases.display());
+}
+
+#[test]
+fn path_macro_records_paths() {
+    // Each path is left in the compiled test, for `cargo xtest-data pack --no-run`.
+    let compiled = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+    for path in ["tests/data.zip", "tests/cases"] {
+        let marker = format!("\0xtest-data-path:{}\0", path);
+        let found = compiled
+            .windows(marker.len())
+            .any(|window| window == marker.as_bytes());
+        assert!(found, "{}", path);
+    }
+}
@@ -0,0 +1,88 @@
+//! Repositories created for a test, and setups that use them instead of this crate.
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args([
+            "-c",
+            "user.name=xtest-data",
+            "-c",
+            "user.email=xtest-data@localhost",
+        ])
+        .args(["-c", "protocol.file.allow=always"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {:?} failed", args);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// A setup of a package in `manifest`, with explicit options as a custom harness would use.
+///
+/// The variables of the environment select the data of this crate's own tests. A packager sets
+/// them, so the scratch repositories must neither be configured by them nor write their packs.
+pub fn builder(name: &str, manifest: &Path, tmpdir: &Path) -> xtest_data::SetupBuilder {
+    // Cargo creates the directory for integration tests.
+    std::fs::create_dir_all(tmpdir).unwrap();
+
+    xtest_data::Setup::builder()
+        .environment(false)
+        .package(name)
+        .repository("https://example.invalid/repository")
+        .manifest_dir(manifest)
+        .tmpdir(tmpdir)
+}
+
+/// A fresh directory for the test.
+pub fn scratch(name: &str) -> PathBuf {
+    let root = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    root
+}
+
+/// A repository in `root/repository` with `files` committed, and its `HEAD`.
+pub fn repository(root: &Path, files: &[(&str, &str)]) -> (PathBuf, String) {
+    let repo = root.join("repository");
+    write_files(&repo, files);
+    git(&repo, &["init", "--quiet"]);
+    let head = commit(&repo, "Data");
+    (repo, head)
+}
+
+/// Write `files`, relative to `dir`, creating their directories.
+pub fn write_files(dir: &Path, files: &[(&str, &str)]) {
+    for (path, content) in files {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+}
+
+/// Commit all changes of the working tree, returning the new `HEAD`.
+pub fn commit(repo: &Path, message: &str) -> String {
+    git(repo, &["add", "--all"]);
+    git(repo, &["commit", "--quiet", "-m", message]);
+    git(repo, &["rev-parse", "HEAD"]).trim().to_owned()
+}
+
+/// Unpack a crate of `commit` into `dir`, without any of its files.
+pub fn crate_archive(dir: &Path, commit: &str) {
+    crate_archive_in(dir, commit, "");
+}
+
+/// Unpack a crate of `commit`, packaged from `path_in_vcs` of its repository, into `dir`.
+pub fn crate_archive_in(dir: &Path, commit: &str, path_in_vcs: &str) {
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::write(
+        dir.join(".cargo_vcs_info.json"),
+        format!(
+            r#"{{ "git": {{ "sha1": "{}" }}, "path_in_vcs": "{}" }}"#,
+            commit.trim(),
+            path_in_vcs
+        ),
+    )
+    .unwrap();
+}
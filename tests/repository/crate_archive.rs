@@ -0,0 +1,44 @@
+//! Data shipped in the crate archive is used without fetching it.
+use crate::common::{builder, crate_archive, repository, scratch};
+
+#[test]
+fn data_in_crate_archive() {
+    let root = scratch("crate-archive");
+
+    let (repo, head) = repository(
+        &root,
+        &[("tests/data/a.txt", "a"), ("tests/data/b.txt", "b")],
+    );
+    let tmpdir = root.join("tmp");
+
+    // Cargo packaged only some of the files, e.g. due to an `include` list.
+    let archive = root.join("archive");
+    crate_archive(&archive, &head);
+    std::fs::create_dir_all(archive.join("tests/data")).unwrap();
+    std::fs::write(archive.join("tests/data/a.txt"), "a").unwrap();
+    let setup = || {
+        builder("crate-archive-test", &archive, &tmpdir)
+            .vcs_info(archive.join(".cargo_vcs_info.json"))
+    };
+
+    // Without any objects, a packaged file of the crate's own commit is trusted.
+    let mut vcs = setup().build();
+    let file = vcs.add("tests/data/a.txt");
+    let data = vcs.build();
+    assert_eq!(data.path(&file), archive.join("tests/data/a.txt"));
+    drop(data);
+
+    // But not a directory, which may be incomplete.
+    let tree = std::panic::catch_unwind(|| {
+        let mut vcs = setup().build();
+        vcs.add("tests/data");
+        vcs.build();
+    });
+    assert!(tree.is_err());
+
+    // With the objects, it is checked and found incomplete.
+    let mut vcs = setup().git_dir(repo.join(".git")).build();
+    let tree = vcs.add("tests/data");
+    let data = vcs.build();
+    assert!(data.path(&tree).join("b.txt").is_file());
+}
@@ -0,0 +1,95 @@
+//! A registered tree can leave out some of the paths below it.
+use crate::common::{builder, crate_archive, repository, scratch};
+use std::path::{Path, PathBuf};
+
+#[test]
+fn add_excluding() {
+    let root = scratch("exclude");
+
+    let (repo, head) = repository(
+        &root,
+        &[
+            ("tests/data/a.txt", "a"),
+            ("tests/data/huge/big.bin", "big"),
+            ("tests/data/huge/more/x.bin", "x"),
+        ],
+    );
+    let tmpdir = root.join("tmp");
+
+    let cases = |data: &xtest_data::FsData, files: &xtest_data::Files| {
+        let mut names = vec![];
+        data.cases(files, |path| {
+            names.push(path.file_name().unwrap().to_owned())
+        });
+        names
+    };
+
+    // Untracked files that were left out do not matter.
+    std::fs::write(repo.join("tests/data/huge/untracked.bin"), "new").unwrap();
+    let mut vcs = builder("exclude-test", &repo, &tmpdir).build();
+    let data_dir = vcs.add_excluding("tests/data", ["tests/data/huge"]);
+    let data = vcs.build();
+    assert_eq!(cases(&data, &data_dir), ["a.txt"]);
+    std::fs::remove_file(repo.join("tests/data/huge/untracked.bin")).unwrap();
+
+    // In a crate archive, the files are not checked out.
+    let archive = root.join("archive");
+    crate_archive(&archive, &head);
+    let from_archive = |also: Option<&str>| {
+        let mut vcs = builder("exclude-test", &archive, &tmpdir)
+            .vcs_info(archive.join(".cargo_vcs_info.json"))
+            .git_dir(repo.join(".git"))
+            .build();
+        let data_dir = vcs.add_excluding("tests/data", ["tests/data/huge/"]);
+        if let Some(also) = also {
+            vcs.add(also);
+        }
+
+        let data = vcs.build();
+        let path: PathBuf = data.path(&data_dir).to_owned();
+        (data, data_dir, path)
+    };
+
+    let (data, data_dir, path) = from_archive(None);
+    assert!(path.join("a.txt").is_file());
+    assert!(!path.join("huge").exists());
+    assert_eq!(cases(&data, &data_dir), ["a.txt"]);
+    drop(data);
+
+    // Data that is registered on its own is never left out.
+    let (data, data_dir, path) = from_archive(Some("tests/data/huge/big.bin"));
+    assert!(path.join("huge/big.bin").is_file());
+    assert!(path.join("huge/more/x.bin").is_file());
+    assert_eq!(cases(&data, &data_dir).len(), 3);
+    drop(data);
+
+    // A pack holds the objects of the remaining data.
+    let pack = root.join("pack");
+    let mut vcs = builder("exclude-test", &repo, &tmpdir)
+        .pack_objects(&pack)
+        .build();
+    vcs.add_excluding("tests/data", ["tests/data/huge"]);
+    vcs.build();
+
+    let mut vcs = builder("exclude-test", &archive, &tmpdir)
+        .vcs_info(archive.join(".cargo_vcs_info.json"))
+        .pack_objects(&pack)
+        .build();
+    let data_dir = vcs.add_excluding("tests/data", ["tests/data/huge"]);
+    let data = vcs.build();
+    assert_eq!(cases(&data, &data_dir), ["a.txt"]);
+    assert!(!data.path(&data_dir).join("huge").exists());
+    drop(data);
+
+    let excluding = |exclude: &'static str| {
+        let (repo, tmpdir) = (repo.clone(), tmpdir.clone());
+        std::panic::catch_unwind(move || {
+            let mut vcs = builder("exclude-test", &repo, &tmpdir).build();
+            vcs.add_excluding("tests/data/huge", [Path::new(exclude)]);
+        })
+    };
+
+    assert!(excluding("tests/data/huge/more").is_ok());
+    assert!(excluding("tests/data/huge").is_err());
+    assert!(excluding("tests/data/a.txt").is_err());
+}
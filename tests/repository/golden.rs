@@ -0,0 +1,50 @@
+//! Golden files, which are blessed only in the repository.
+use crate::common::{builder, crate_archive, repository, scratch};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+#[test]
+fn bless_golden_files() {
+    let root = scratch("golden");
+
+    let (repo, commit) = repository(&root, &[("tests/golden/out.txt", "line 1\nline 2\n")]);
+
+    let packs = root.join("packs");
+    let local = || {
+        builder("golden-test", &repo, &root.join("local"))
+            .pack_objects(&packs)
+            .read_only(true)
+    };
+    let mut vcs = local().bless(false).build();
+    let golden = vcs.add("tests/golden/out.txt");
+    let local_data = vcs.build();
+
+    local_data.assert_matches(&golden, b"line 1\nline 2\n");
+    let differs = catch_unwind(AssertUnwindSafe(|| {
+        local_data.assert_matches(&golden, b"line 1\nchanged\n");
+    }));
+    assert!(differs.is_err());
+
+    // A crate archive has no working tree to update.
+    let archive = root.join("crate");
+    crate_archive(&archive, &commit);
+    let mut vcs = builder("golden-test", &archive, &root.join("archive"))
+        .pack_objects(&packs)
+        .bless(true)
+        .build();
+    let packaged = vcs.add("tests/golden/out.txt");
+    let packaged_data = vcs.build();
+
+    let refused = catch_unwind(AssertUnwindSafe(|| {
+        packaged_data.assert_matches(&packaged, b"line 1\nchanged\n");
+    }));
+    assert!(refused.is_err());
+    let unchanged = std::fs::read_to_string(packaged_data.path(&packaged)).unwrap();
+    assert_eq!(unchanged, "line 1\nline 2\n");
+
+    // The repository is updated, even though its data was marked read-only.
+    let mut vcs = local().bless(true).build();
+    let golden = vcs.add("tests/golden/out.txt");
+    vcs.build().assert_matches(&golden, b"line 1\nchanged\n");
+    let blessed = std::fs::read_to_string(repo.join("tests/golden/out.txt")).unwrap();
+    assert_eq!(blessed, "line 1\nchanged\n");
+}
@@ -0,0 +1,130 @@
+//! The checkout of a crate archive is kept only for failed tests.
+use crate::common::{builder, commit, crate_archive, repository, scratch};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+#[test]
+fn tree_kept_on_failure() {
+    let root = scratch("keep");
+
+    let (repo, commit) = repository(&root, &[("tests/data/a.txt", "a")]);
+
+    let packs = root.join("packs");
+    let mut vcs = builder("keep-test", &repo, &root.join("local"))
+        .pack_objects(&packs)
+        .build();
+    vcs.add("tests/data");
+    vcs.build();
+
+    let archive = root.join("crate");
+    crate_archive(&archive, &commit);
+    let setup = || {
+        builder("keep-test", &archive, &root.join("archive"))
+            .pack_objects(&packs)
+            .build()
+    };
+
+    // A passing test leaves nothing behind.
+    let mut vcs = setup();
+    let data = vcs.add("tests/data");
+    let testdata = vcs.build();
+    let tree = testdata.path(&data).to_owned();
+    assert!(tree.join("a.txt").exists());
+    drop(testdata);
+    assert!(!tree.exists(), "{}", tree.display());
+
+    // A failing test keeps the tree, with a mapping to reuse it.
+    let tree = Mutex::new(PathBuf::new());
+    let failed = std::panic::catch_unwind(|| {
+        let mut vcs = setup();
+        let data = vcs.add("tests/data");
+        let testdata = vcs.build();
+        *tree.lock().unwrap() = testdata.path(&data).to_owned();
+        panic!("The test failed");
+    });
+    assert!(failed.is_err());
+
+    let tree = tree.into_inner().unwrap();
+    assert!(tree.join("a.txt").exists(), "{}", tree.display());
+    let mapping = tree.parent().unwrap().parent().unwrap();
+    let mapping = std::fs::read_to_string(mapping.join("xtest-data-tree.json")).unwrap();
+    assert!(mapping.contains(commit.trim()), "{}", mapping);
+    assert!(mapping.contains("tests/data/a.txt"), "{}", mapping);
+
+    // Paths of the caller that were rewritten into the tree outlive the data, until the end of
+    // the thread of the test. They are kept if it failed.
+    let rewrite = |fail: bool| {
+        let path = Mutex::new(PathBuf::from("tests/data/a.txt"));
+        let test = std::thread::scope(|scope| {
+            let test = scope.spawn(|| {
+                let mut path = path.lock().unwrap();
+                setup().rewrite([&mut *path]).build();
+                assert!(path.exists(), "{}", path.display());
+                drop(path);
+                assert!(!fail, "The test failed");
+            });
+            test.join()
+        });
+        assert_eq!(test.is_err(), fail);
+        path.into_inner().unwrap()
+    };
+
+    let path = rewrite(false);
+    assert!(!path.exists(), "{}", path.display());
+    let path = rewrite(true);
+    assert!(path.exists(), "{}", path.display());
+}
+
+#[test]
+fn reproduction_steps() {
+    // The child fails with data selected by its environment, as in a packaged crate.
+    if let Some(root) = std::env::var_os("XTEST_DATA_REPRODUCE") {
+        let root = PathBuf::from(root);
+        let mut vcs = builder("keep-test", &root.join("crate"), &root.join("archive"))
+            .environment(true)
+            .pack_objects(root.join("packs"))
+            .build();
+        vcs.add("tests/data");
+        let _testdata = vcs.build();
+        panic!("The test failed");
+    }
+
+    let root = scratch("reproduce");
+
+    let (repo, packaged) = repository(&root, &[("tests/data/a.txt", "a")]);
+    std::fs::write(repo.join("tests/data/a.txt"), "b").unwrap();
+    let commit = commit(&repo, "Changed data");
+
+    let packs = root.join("packs");
+    let mut vcs = builder("keep-test", &repo, &root.join("local"))
+        .pack_objects(&packs)
+        .build();
+    vcs.add("tests/data");
+    vcs.build();
+    crate_archive(&root.join("crate"), &packaged);
+
+    // Only the commit is selected by the environment, not by that of our own tests.
+    let mut child = std::process::Command::new(std::env::current_exe().unwrap());
+    for (name, _) in std::env::vars_os() {
+        if name.to_string_lossy().starts_with("CARGO_XTEST") {
+            child.env_remove(name);
+        }
+    }
+    let output = child
+        .args(["keep::reproduction_steps", "--exact", "--test-threads=1"])
+        .env("XTEST_DATA_REPRODUCE", &root)
+        .env("CARGO_XTEST_DATA_COMMIT", &commit)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reproduce = format!(
+        "cd {} && CARGO_XTEST_DATA_PACK_OBJECTS={} CARGO_XTEST_DATA_COMMIT={} cargo test -- keep::reproduction_steps --exact",
+        root.join("crate").display(),
+        packs.display(),
+        commit,
+    );
+    assert!(stdout.contains(&reproduce), "{}\n{}", stdout, stderr);
+}
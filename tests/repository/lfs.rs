@@ -0,0 +1,58 @@
+//! Data stored with Git LFS, in a repository created for the test.
+//!
+//! The test does not require `git lfs`, it writes the pointer and the local object itself.
+use crate::common::{builder, crate_archive, repository, scratch};
+
+const CONTENT: &str = "large binary fixture\n";
+const OID: &str = "ab0a7aa2f500fbfbdbb70cee1745f2680fe4ceffd09054ac0add2b0898325a07";
+
+#[test]
+fn data_in_lfs() {
+    let root = scratch("lfs");
+
+    let pointer = format!(
+        "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+        OID,
+        CONTENT.len()
+    );
+    let (repo, commit) = repository(&root, &[("tests/fixture.bin", &pointer)]);
+
+    let objects = repo
+        .join(".git/lfs/objects")
+        .join(&OID[0..2])
+        .join(&OID[2..4]);
+    std::fs::create_dir_all(&objects).unwrap();
+    std::fs::write(objects.join(OID), CONTENT).unwrap();
+
+    let packs = root.join("packs");
+
+    // From the repository, which writes the packs and copies the object.
+    let mut vcs = builder("lfs-test", &repo, &root.join("local"))
+        .pack_objects(&packs)
+        .build();
+    vcs.add("tests/fixture.bin");
+    vcs.build();
+
+    let packed = packs
+        .join(commit.trim())
+        .join("lfs/objects")
+        .join(&OID[0..2])
+        .join(&OID[2..4])
+        .join(OID);
+    assert_eq!(std::fs::read_to_string(packed).unwrap(), CONTENT);
+
+    // From a crate archive, where the pointer is replaced by the object.
+    let archive = root.join("crate");
+    crate_archive(&archive, &commit);
+
+    let mut vcs = builder("lfs-test", &archive, &root.join("archive"))
+        .pack_objects(&packs)
+        .build();
+    let fixture = vcs.add("tests/fixture.bin");
+    let testdata = vcs.build();
+
+    assert_eq!(
+        std::fs::read_to_string(testdata.path(&fixture)).unwrap(),
+        CONTENT
+    );
+}
@@ -0,0 +1,40 @@
+//! Symbolic links in the data must point to registered paths.
+#![cfg(unix)]
+use crate::common::{builder, commit, git, scratch, write_files};
+use std::os::unix::fs::symlink;
+
+#[test]
+fn links_out_of_registered_paths() {
+    let root = scratch("links");
+
+    let repo = root.join("repository");
+    write_files(
+        &repo,
+        &[("tests/data/a.txt", "a"), ("tests/other/b.txt", "b")],
+    );
+    symlink("a.txt", repo.join("tests/data/inside")).unwrap();
+    symlink("../other/b.txt", repo.join("tests/data/sibling")).unwrap();
+    symlink("/etc/hostname", repo.join("tests/data/absolute")).unwrap();
+    symlink("../../../outside", repo.join("tests/data/escaping")).unwrap();
+    git(&repo, &["init", "--quiet"]);
+    commit(&repo, "Links");
+
+    let build = |paths: &'static [&'static str]| {
+        let (repo, tmpdir) = (repo.clone(), root.join("tmp"));
+        std::panic::catch_unwind(move || {
+            let mut vcs = builder("links-test", &repo, &tmpdir).build();
+            for path in paths {
+                vcs.add(path);
+            }
+            vcs.build();
+        })
+    };
+
+    assert!(build(&["tests/data/a.txt", "tests/data/inside"]).is_ok());
+    // The directory also contains the links below.
+    assert!(build(&["tests/data", "tests/other"]).is_err());
+    assert!(build(&["tests/data/sibling", "tests/other"]).is_ok());
+    assert!(build(&["tests/data/sibling"]).is_err());
+    assert!(build(&["tests/data/absolute"]).is_err());
+    assert!(build(&["tests/data/escaping"]).is_err());
+}
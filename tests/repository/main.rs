@@ -0,0 +1,16 @@
+//! Tests in repositories created for them, instead of the repository of this crate.
+mod common;
+
+mod crate_archive;
+mod exclude;
+mod golden;
+mod keep;
+mod lfs;
+mod links;
+mod metadata;
+mod object_id;
+mod paths;
+mod read_only;
+mod sparse;
+mod submodule;
+mod unchanged;
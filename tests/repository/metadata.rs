@@ -0,0 +1,88 @@
+//! The manifest of a crate archive configures where its pack objects are published.
+use crate::common::{builder, crate_archive, repository, scratch};
+
+const MANIFEST: &str = r#"[package]
+name = "metadata-test"
+version = "0.1.0"
+repository = "https://example.invalid/repository"
+
+[package.metadata.xtest-data]
+pack-archive = "tar:gz"
+pack-artifact = "{repository}/releases/download/v{version}/xtest-data.tar.gz"
+pack-objects = 'target/xtest-data-pack' # Relative to the manifest.
+"#;
+
+#[test]
+fn metadata_of_the_manifest() {
+    let root = scratch("metadata");
+
+    let (repo, head) = repository(&root, &[("tests/data/a.txt", "a")]);
+
+    let archive = root.join("archive");
+    crate_archive(&archive, &head);
+    std::fs::write(archive.join("Cargo.toml"), MANIFEST).unwrap();
+    let tmpdir = root.join("tmp");
+    let packs = archive.join("target/xtest-data-pack");
+
+    // Without the pack objects, the error says where to get them.
+    let missing = std::panic::catch_unwind(|| {
+        let mut vcs = builder("metadata-test", &archive, &tmpdir).build();
+        vcs.add("tests/data");
+        vcs.build();
+    });
+
+    let message = missing.unwrap_err().downcast::<String>().unwrap();
+    let url = "https://example.invalid/repository/releases/download/v0.1.0/xtest-data.tar.gz";
+    let fetch = format!(
+        "cargo xtest-data fetch {} {}",
+        archive.display(),
+        packs.display()
+    );
+    assert!(message.contains(url), "{}", message);
+    assert!(message.contains(&fetch), "{}", message);
+
+    // A fallible build returns the same message.
+    let mut vcs = builder("metadata-test", &archive, &tmpdir).build();
+    vcs.add("tests/data");
+    let err = vcs.try_build().unwrap_err();
+    assert!(err.to_string().contains(&fetch), "{}", err);
+
+    // Once they are in the configured directory, they are used.
+    let mut vcs = builder("metadata-test", &repo, &tmpdir)
+        .pack_objects(&packs)
+        .build();
+    vcs.add("tests/data");
+    vcs.build();
+
+    let mut vcs = builder("metadata-test", &archive, &tmpdir).build();
+    let data = vcs.add("tests/data");
+    let testdata = vcs.build();
+    let path = testdata.path(&data).join("a.txt");
+    assert_eq!(std::fs::read(path).unwrap(), b"a");
+}
+
+#[test]
+fn metadata_of_this_manifest() {
+    // The xtask renders the same templates of our own manifest with a TOML parser.
+    let root = scratch("metadata-ours");
+    let archive = root.join("archive");
+    crate_archive(&archive, "0000000000000000000000000000000000000000");
+    let manifest = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    std::fs::copy(manifest, archive.join("Cargo.toml")).unwrap();
+
+    let missing = std::panic::catch_unwind(|| {
+        let mut vcs = builder("xtest-data", &archive, &root.join("tmp")).build();
+        vcs.add("tests/data.zip");
+        vcs.build();
+    });
+
+    let message = missing.unwrap_err().downcast::<String>().unwrap();
+    let url = format!(
+        "{}/releases/download/v{}/xtest-data.tar.gz",
+        env!("CARGO_PKG_REPOSITORY"),
+        env!("CARGO_PKG_VERSION"),
+    );
+    let packs = archive.join("target/xtest-data-pack");
+    assert!(message.contains(&url), "{}", message);
+    assert!(message.contains(&*packs.to_string_lossy()), "{}", message);
+}
@@ -0,0 +1,49 @@
+//! Object ids and the commit of data, in a repository created for the test.
+use crate::common::{builder, crate_archive, git, repository, scratch};
+
+#[test]
+fn object_ids_of_data() {
+    let root = scratch("object-id");
+
+    let (repo, commit) = repository(&root, &[("tests/data/a.txt", "a")]);
+    let blob = git(&repo, &["rev-parse", "HEAD:tests/data/a.txt"]);
+    let tree = git(&repo, &["rev-parse", "HEAD:tests/data"]);
+
+    let packs = root.join("packs");
+    let mut vcs = builder("object-id-test", &repo, &root.join("local"))
+        .pack_objects(&packs)
+        .build();
+    let file = vcs.add("tests/data/a.txt");
+    let dir = vcs.add("tests/data");
+    let local = vcs.build();
+
+    assert_eq!(local.commit(), Some(commit.trim()));
+    let id = local.object_id(&file).unwrap();
+    assert_eq!(id.as_str(), blob.trim());
+    assert!(!id.is_dirty());
+    assert_eq!(local.object_id(&dir).unwrap().to_string(), tree.trim());
+
+    // The same objects in a crate archive.
+    let archive = root.join("crate");
+    crate_archive(&archive, &commit);
+    let mut vcs = builder("object-id-test", &archive, &root.join("archive"))
+        .pack_objects(&packs)
+        .build();
+    let file = vcs.add("tests/data/a.txt");
+    let dir = vcs.add("tests/data");
+    let packaged = vcs.build();
+
+    assert_eq!(packaged.commit(), Some(commit.trim()));
+    assert_eq!(packaged.object_id(&file).unwrap().as_str(), blob.trim());
+    assert_eq!(packaged.object_id(&dir).unwrap().as_str(), tree.trim());
+
+    // Local changes are not described by the id.
+    let mut vcs = builder("object-id-test", &repo, &root.join("local")).build();
+    let file = vcs.add("tests/data/a.txt");
+    let local = vcs.build();
+    std::fs::write(repo.join("tests/data/a.txt"), "changed").unwrap();
+
+    let id = local.object_id(&file).unwrap();
+    assert_eq!(id.as_str(), blob.trim());
+    assert!(id.is_dirty());
+}
@@ -0,0 +1,80 @@
+//! Registered paths are relative to the manifest, but must stay within the repository.
+use crate::common::{builder, crate_archive_in, repository, scratch};
+use std::path::PathBuf;
+
+#[test]
+fn paths_of_a_workspace_member() {
+    let root = scratch("paths");
+
+    let (repo, head) = repository(
+        &root,
+        &[
+            ("crates/member/tests/own.txt", "own"),
+            ("shared/data.txt", "shared"),
+        ],
+    );
+    let member = repo.join("crates/member");
+
+    let check = |mut vcs: xtest_data::Setup| {
+        let tests = vcs.add("tests");
+        let own = vcs.add("tests/./own.txt");
+        let shared = vcs.add("../../shared/data.txt");
+        let mut rewritten = PathBuf::from("../member/../../shared");
+        let data = vcs.rewrite([&mut rewritten]).build();
+
+        assert_eq!(std::fs::read(data.path(&own)).unwrap(), b"own");
+        assert_eq!(std::fs::read(data.path(&shared)).unwrap(), b"shared");
+        assert!(rewritten.join("data.txt").is_file());
+
+        let mut cases = vec![];
+        data.cases(&tests, |path| cases.push(path.to_owned()));
+        assert_eq!(cases, [data.path(&own)]);
+
+        let resolved = data.resolve("../../shared/./data.txt").unwrap();
+        assert_eq!(resolved, data.path(&shared));
+
+        let copy = data.scratch_copy(&shared);
+        assert!(copy.starts_with(root.join("tmp")), "{}", copy.display());
+        assert_eq!(std::fs::read(copy).unwrap(), b"shared");
+    };
+
+    // In the repository itself.
+    check(builder("paths-test", &member, &root.join("tmp")).build());
+
+    // In a crate archive of the member, with the data from a local clone.
+    let archive = root.join("archive");
+    crate_archive_in(&archive, &head, "crates/member");
+    check(
+        builder("paths-test", &archive, &root.join("tmp"))
+            .vcs_info(archive.join(".cargo_vcs_info.json"))
+            .git_dir(repo.join(".git"))
+            .build(),
+    );
+
+    // A tree without any files has no cases, which is an error.
+    let mut vcs = builder("paths-test", &member, &root.join("tmp")).build();
+    let nothing = vcs.add_excluding("tests", ["tests/own.txt"]);
+    let data = vcs.build();
+    let no_cases = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        data.cases(&nothing, |_| {});
+    }));
+    assert!(no_cases.is_err());
+
+    let add = |path: &'static str| {
+        let (member, tmpdir) = (member.clone(), root.join("tmp"));
+        std::panic::catch_unwind(move || {
+            builder("paths-test", &member, &tmpdir).build().add(path);
+        })
+    };
+
+    assert!(add("../../shared").is_ok());
+    assert!(add("../member/tests").is_ok());
+    assert!(add("../../../outside").is_err());
+    assert!(add("../../shared/../../outside").is_err());
+    assert!(add(if cfg!(windows) {
+        "C:\\data"
+    } else {
+        "/etc/hostname"
+    })
+    .is_err());
+}
@@ -0,0 +1,28 @@
+//! Read-only data, and the writable scratch copies of it.
+use crate::common::{builder, repository, scratch};
+
+#[test]
+fn read_only_data() {
+    // This changes the permissions of the working tree, which must not be our own.
+    let root = scratch("read-only");
+
+    let (repo, _) = repository(&root, &[("tests/data/a.txt", "a")]);
+    let tmpdir = root.join("tmp");
+
+    let mut vcs = builder("read-only-test", &repo, &tmpdir)
+        .read_only(true)
+        .build();
+    let data = vcs.add("tests/data");
+    let testdata = vcs.build();
+
+    let original = testdata.path(&data).join("a.txt");
+    let readonly =
+        |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().readonly();
+    assert!(readonly(&original));
+
+    let scratch = testdata.scratch_copy(&data);
+    assert!(scratch.ends_with("tests/data"), "{}", scratch.display());
+    assert!(!readonly(&scratch.join("a.txt")));
+    std::fs::write(scratch.join("a.txt"), "modified").unwrap();
+    assert_eq!(std::fs::read_to_string(&original).unwrap(), "a");
+}
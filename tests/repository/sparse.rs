@@ -0,0 +1,87 @@
+//! A crate only checks out the data it registered, not the whole tree of its commit.
+use crate::common::{builder, crate_archive, git, repository, scratch};
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn sparse_checkout() {
+    let root = scratch("sparse");
+
+    let (repo, head) = repository(
+        &root,
+        &[
+            ("tests/data/a.txt", "a"),
+            ("other/b.txt", "b"),
+            // Patterns are anchored, a name does not match in other directories.
+            ("config.txt", "top"),
+            ("other/config.txt", "nested"),
+        ],
+    );
+    let tmpdir = root.join("tmp");
+
+    let archive = root.join("archive");
+    crate_archive(&archive, &head);
+
+    // Objects borrowed from a clone.
+    let mut vcs = builder("sparse-test", &archive, &tmpdir)
+        .vcs_info(archive.join(".cargo_vcs_info.json"))
+        .git_dir(repo.join(".git"))
+        .build();
+    let data_dir = vcs.add("tests/data");
+    vcs.add("config.txt");
+    let data = vcs.build();
+    let checkout = data.path(&data_dir).join("../..");
+    assert!(checkout.join("tests/data/a.txt").is_file());
+    assert!(checkout.join("config.txt").is_file());
+    assert!(!checkout.join("other").exists());
+    drop(data);
+
+    // Objects from a pack, written with a sparse filter of the registered paths.
+    let pack = root.join("pack");
+    let mut vcs = builder("sparse-test", &repo, &tmpdir)
+        .pack_objects(&pack)
+        .build();
+    vcs.add("tests/data");
+    vcs.add("config.txt");
+    vcs.build();
+
+    let objects = packed_objects(&pack);
+    let blob = |path: &str| git(&repo, &["rev-parse", &format!("HEAD:{}", path)]);
+    assert!(objects.contains(blob("tests/data/a.txt").trim()));
+    assert!(objects.contains(blob("config.txt").trim()));
+    assert!(!objects.contains(blob("other/b.txt").trim()));
+    assert!(!objects.contains(blob("other/config.txt").trim()));
+
+    let mut vcs = builder("sparse-test", &archive, &tmpdir)
+        .vcs_info(archive.join(".cargo_vcs_info.json"))
+        .pack_objects(&pack)
+        .build();
+    let data_dir = vcs.add("tests/data");
+    vcs.add("config.txt");
+    let data = vcs.build();
+    let checkout = data.path(&data_dir).join("../..");
+    assert!(checkout.join("tests/data/a.txt").is_file());
+    assert!(checkout.join("config.txt").is_file());
+    assert!(!checkout.join("other").exists());
+}
+
+/// The ids of all objects in the packs below `dir`.
+fn packed_objects(dir: &Path) -> String {
+    let mut objects = String::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            objects.push_str(&packed_objects(&path));
+        } else if path.extension().is_some_and(|ext| ext == "idx") {
+            let index = std::fs::File::open(&path).unwrap();
+            let output = Command::new("git")
+                .arg("show-index")
+                .stdin(index)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            objects.push_str(&String::from_utf8(output.stdout).unwrap());
+        }
+    }
+    objects
+}
@@ -0,0 +1,62 @@
+//! Data in a submodule, in a repository created for the test.
+use crate::common::{builder, commit, crate_archive, git, scratch, write_files};
+
+#[test]
+fn data_in_submodule() {
+    let root = scratch("submodule");
+
+    let corpus = root.join("corpus");
+    write_files(&corpus, &[("samples/a.txt", "from the submodule")]);
+    git(&corpus, &["init", "--quiet"]);
+    commit(&corpus, "Corpus");
+
+    let superproject = root.join("superproject");
+    write_files(&superproject, &[("Readme.md", "superproject")]);
+    git(&superproject, &["init", "--quiet"]);
+    git(
+        &superproject,
+        &[
+            "submodule",
+            "add",
+            "--quiet",
+            corpus.to_str().unwrap(),
+            "tests/corpus",
+        ],
+    );
+    let commit = commit(&superproject, "Superproject");
+    let samples_tree = git(&corpus, &["rev-parse", "HEAD:samples"]);
+
+    let packs = root.join("packs");
+
+    // From the repository, which writes the packs.
+    let mut vcs = builder("submodule-test", &superproject, &root.join("local"))
+        .pack_objects(&packs)
+        .build();
+    let samples = vcs.add("tests/corpus/samples");
+    let testdata = vcs.build();
+
+    let mut seen = vec![];
+    testdata.cases(&samples, |path| {
+        seen.push(std::fs::read_to_string(path).unwrap());
+    });
+    assert_eq!(seen, ["from the submodule"]);
+    let id = testdata.object_id(&samples).unwrap();
+    assert_eq!(id.as_str(), samples_tree.trim());
+    assert!(!id.is_dirty());
+
+    // From a crate archive, which only has the commit of the superproject.
+    let archive = root.join("crate");
+    crate_archive(&archive, &commit);
+
+    let mut vcs = builder("submodule-test", &archive, &root.join("archive"))
+        .pack_objects(&packs)
+        .build();
+    let samples = vcs.add("tests/corpus/samples");
+    let testdata = vcs.build();
+
+    let path = testdata.path(&samples).join("a.txt");
+    assert!(path.starts_with(root.join("archive")), "{}", path.display());
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "from the submodule");
+    let id = testdata.object_id(&samples).unwrap();
+    assert_eq!(id.as_str(), samples_tree.trim());
+}
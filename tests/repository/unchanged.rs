@@ -0,0 +1,47 @@
+//! Data found clean once is not checked with git again, until any of its files change.
+use crate::common::{builder, git, repository, scratch};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+#[test]
+fn changes_after_a_clean_check() {
+    let root = scratch("unchanged");
+
+    let (repo, _) = repository(&root, &[("tests/data/a.txt", "a")]);
+
+    let packs = root.join("packs");
+    let build = || {
+        catch_unwind(AssertUnwindSafe(|| {
+            let mut vcs = builder("unchanged-test", &repo, &root.join("tmp"))
+                .pack_objects(&packs)
+                .build();
+            vcs.add("tests/data");
+            vcs.build();
+        }))
+    };
+
+    // A check in the same second as a change is not remembered, as the change could be missed.
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    assert!(build().is_ok());
+    let markers = std::fs::read_dir(root.join("tmp/xtest-data-clean")).unwrap();
+    assert_eq!(markers.count(), 1);
+    assert!(!repo.join(".git/xtest-data").exists());
+    assert!(build().is_ok());
+
+    // Modified, then restored.
+    std::fs::write(repo.join("tests/data/a.txt"), "modified").unwrap();
+    assert!(build().is_err());
+    std::fs::write(repo.join("tests/data/a.txt"), "a").unwrap();
+    assert!(build().is_ok());
+
+    // Untracked.
+    std::fs::write(repo.join("tests/data/b.txt"), "b").unwrap();
+    assert!(build().is_err());
+    std::fs::remove_file(repo.join("tests/data/b.txt")).unwrap();
+    assert!(build().is_ok());
+
+    // Staged, with the same content in the working tree.
+    std::fs::write(repo.join("tests/data/a.txt"), "staged").unwrap();
+    git(&repo, &["add", "tests/data/a.txt"]);
+    std::fs::write(repo.join("tests/data/a.txt"), "a").unwrap();
+    assert!(build().is_err());
+}